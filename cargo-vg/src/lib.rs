@@ -1,11 +1,16 @@
 use std::{
+    net::SocketAddr,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
     rc::Rc,
     sync::mpsc::TryRecvError,
+    time::Duration,
 };
 use structopt::StructOpt;
-use vg_native::runtime::wasm::Wasm;
+use vg_native::{
+    runtime::{dylib::Dylib, wasm::Wasm},
+    Headless, NetOptions, Options,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(bin_name = "cargo")]
@@ -27,7 +32,7 @@ pub struct Opts {
 #[derive(Debug, Clone, StructOpt)]
 pub enum Cmd {
     /// Build the project and launch it
-    Run,
+    Run(RunOpts),
     /// Run the project on every file change
     Watch,
     /// Build the project
@@ -36,6 +41,131 @@ pub enum Cmd {
     Clean,
     /// Build the game for web deployment
     Web,
+    /// Pack assets into a single archive to ship with the game
+    Pack {
+        /// Directory of assets to pack, `assets` next to the manifest by default
+        #[structopt(long)]
+        dir: Option<PathBuf>,
+        /// Archive to write
+        #[structopt(long, default_value = "assets.vgpak")]
+        out: PathBuf,
+        /// Deflate assets that get noticeably smaller from it
+        #[structopt(long)]
+        compress: bool,
+    },
+    /// Build the project and run it without a window or audio device
+    Headless {
+        /// Number of fixed ticks to run
+        #[structopt(long, default_value = "600")]
+        ticks: usize,
+        /// Draw the last tick with the software renderer and save it here
+        #[structopt(long)]
+        screenshot: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Default, StructOpt)]
+pub struct RunOpts {
+    /// Build the game for the host and run it natively, for debuggers and speed
+    #[structopt(long)]
+    pub native: bool,
+    /// Record all game input into a replay file
+    #[structopt(long)]
+    pub record: Option<PathBuf>,
+    /// Play back input from a replay file instead of the keyboard
+    #[structopt(long, conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+    /// Addresses of every player in an online session, in player order
+    #[structopt(long, use_delimiter = true, conflicts_with_all = &["record", "replay"])]
+    pub net_players: Vec<SocketAddr>,
+    /// Which of the online players we are
+    #[structopt(long, default_value = "0")]
+    pub net_local: usize,
+    /// Ticks to delay local input by in online sessions
+    #[structopt(long, default_value = "2")]
+    pub net_delay: u64,
+    /// Simulated network latency in milliseconds
+    #[structopt(long, default_value = "0")]
+    pub sim_latency: u64,
+    /// Simulated fraction of lost packets, between 0 and 1
+    #[structopt(long, default_value = "0")]
+    pub sim_loss: f32,
+    /// Save every presented frame as a PNG into this directory
+    #[structopt(long)]
+    pub capture_frames: Option<PathBuf>,
+    /// Directory whose assets take priority over the game's own, can be given
+    /// multiple times
+    #[structopt(long = "assets-override")]
+    pub asset_overrides: Vec<PathBuf>,
+}
+
+impl RunOpts {
+    fn engine_options(&self, opts: &Opts) -> Options {
+        let net = if self.net_players.is_empty() {
+            None
+        } else {
+            Some(NetOptions {
+                players: self.net_players.clone(),
+                local: self.net_local,
+                input_delay: self.net_delay,
+                sim_latency: Duration::from_millis(self.sim_latency),
+                sim_loss: self.sim_loss,
+            })
+        };
+
+        Options {
+            record: self.record.clone(),
+            replay: self.replay.clone(),
+            net,
+            capture_frames: self.capture_frames.clone(),
+            asset_paths: asset_paths(&opts.manifest_path, &self.asset_overrides),
+            embedded_assets: &[],
+        }
+    }
+}
+
+/// Asset directories of the project, in priority order, with `overrides` from
+/// the command line first
+fn asset_dirs(manifest: &Path, overrides: &[PathBuf]) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = overrides.to_vec();
+    match vg_types::asset_dirs(manifest) {
+        Ok(dirs) => paths.extend(dirs),
+        Err(e) => {
+            println!("{}", e);
+            let root = manifest.parent().unwrap_or_else(|| Path::new(""));
+            paths.push(root.join("assets"));
+        }
+    }
+
+    paths
+}
+
+/// Where small images of the project are packed into a texture atlas, unless
+/// `atlas = false` is set
+fn atlas_dir(manifest: &Path) -> Option<PathBuf> {
+    let atlas = vg_types::metadata(manifest)
+        .ok()
+        .flatten()
+        .and_then(|vg| vg.get("atlas")?.as_bool())
+        .unwrap_or(true);
+
+    let root = manifest.parent().unwrap_or_else(|| Path::new(""));
+    Some(root.join("target").join("vg-atlas")).filter(|_| atlas)
+}
+
+/// Asset search paths of the project: its asset directories, then the texture
+/// atlas built from them
+fn asset_paths(manifest: &Path, overrides: &[PathBuf]) -> Vec<PathBuf> {
+    let mut paths = asset_dirs(manifest, overrides);
+
+    if let Some(out) = atlas_dir(manifest) {
+        match vg_native::build_atlas(&paths, &out) {
+            Ok(()) => paths.push(out),
+            Err(e) => println!("Failed to build texture atlas: {}", e),
+        }
+    }
+
+    paths
 }
 
 fn run_cargo(
@@ -79,24 +209,98 @@ fn read_wasm() -> Vec<u8> {
     std::fs::read("target/wasm32-wasi/debug/rust-test.wasm").unwrap()
 }
 
+/// Build the game for the host as a native library, returning the path of
+/// the library
+fn build_native(manifest: impl AsRef<Path>, build: Option<PathBuf>) -> Option<PathBuf> {
+    let mut cmd = std::process::Command::new("cargo");
+
+    cmd.arg("rustc")
+        .arg("--manifest-path")
+        .arg(manifest.as_ref())
+        .arg("--features")
+        .arg("vg/dylib")
+        .arg("--message-format=json-render-diagnostics")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+
+    if let Some(path) = build {
+        cmd.arg("--target-dir").arg(path);
+    }
+
+    // the game is a binary, have rustc emit a library next to it
+    cmd.arg("--").arg("--crate-type=cdylib");
+
+    let output = cmd.output().unwrap();
+    if !output.status.success() {
+        return None;
+    }
+
+    // cargo only reports the binary, the last artifact with an executable
+    let (name, executable) = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter_map(|message| {
+            let name = message["target"]["name"].as_str()?.to_owned();
+            Some((name, PathBuf::from(message["executable"].as_str()?)))
+        })
+        .last()?;
+
+    let library = find_native(&name, &executable);
+    if library.is_none() {
+        println!("Native build of {} produced no library", name);
+    }
+    library
+}
+
+/// The library rustc left in deps next to the binary of a target
+fn find_native(name: &str, executable: &Path) -> Option<PathBuf> {
+    use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+
+    let deps = executable.parent()?.join("deps");
+    let prefix = format!("{}{}-", DLL_PREFIX, name.replace('-', "_"));
+
+    // older builds stay around, pick the latest
+    std::fs::read_dir(deps)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with(&prefix) && name.ends_with(DLL_SUFFIX)
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .map(|entry| entry.path())
+}
+
+fn run_project(opts: &Opts, run: &RunOpts) {
+    if run.native {
+        if let Some(library) = build_native(&opts.manifest_path, opts.build_path.clone()) {
+            println!("Running project natively");
+            let mut code = Some(std::fs::read(library).unwrap());
+            vg_native::Engine::run_with::<Dylib, _>(run.engine_options(opts), move || code.take());
+        }
+        return;
+    }
+
+    if run_cargo(&opts.manifest_path, opts.build_path.clone(), "build", None) {
+        println!("Running project");
+        let mut wasm = Some(read_wasm());
+        vg_native::Engine::run_with::<Wasm, _>(run.engine_options(opts), move || wasm.take());
+    }
+}
+
 pub fn run(opts: Opts) {
     // let existing = std::env::var("RUSTFLAGS").unwrap_or_default();
     // std::env::set_var("RUSTFLAGS", "-C link-arg=--import-memory");
 
     match opts.cmd {
-        None | Some(Cmd::Run) => {
-            if run_cargo(&opts.manifest_path, opts.build_path, "build", None) {
-                println!("Running project");
-                let mut wasm = Some(read_wasm());
-                vg_native::Engine::run::<Wasm, _>(move || wasm.take());
-            }
-        }
+        None => run_project(&opts, &RunOpts::default()),
+        Some(Cmd::Run(ref run)) => run_project(&opts, run),
         Some(Cmd::Watch) => {
             println!("Watching project for changes");
 
             use notify::{watcher, RecursiveMode, Watcher};
             use std::sync::mpsc::channel;
-            use std::time::Duration;
 
             let (tx, rx) = channel();
             let rx = Rc::new(rx);
@@ -113,21 +317,36 @@ pub fn run(opts: Opts) {
                 )
                 .unwrap();
 
-            vg_native::Engine::run::<Wasm, _>(move || match rx.try_recv() {
-                Ok(_) => {
-                    if run_cargo(
-                        &opts.manifest_path,
-                        opts.build_path.clone(),
-                        "build",
-                        None,
-                    ) {
-                        Some(read_wasm())
-                    } else {
-                        None
+            // images are packed again as they change, the engine picks the
+            // new atlas up like any other changed asset
+            let (asset_tx, asset_rx) = channel();
+            let mut asset_watcher = watcher(asset_tx, Duration::from_millis(500)).unwrap();
+            let dirs = asset_dirs(&opts.manifest_path, &[]);
+            for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+                asset_watcher.watch(dir, RecursiveMode::Recursive).unwrap();
+            }
+            let atlas = atlas_dir(&opts.manifest_path);
+
+            let options = RunOpts::default().engine_options(&opts);
+            vg_native::Engine::run_with::<Wasm, _>(options, move || {
+                if let (Ok(_), Some(out)) = (asset_rx.try_recv(), &atlas) {
+                    while asset_rx.try_recv().is_ok() {}
+                    if let Err(e) = vg_native::build_atlas(&dirs, out) {
+                        println!("Failed to build texture atlas: {}", e);
+                    }
+                }
+
+                match rx.try_recv() {
+                    Ok(_) => {
+                        if run_cargo(&opts.manifest_path, opts.build_path.clone(), "build", None) {
+                            Some(read_wasm())
+                        } else {
+                            None
+                        }
                     }
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => panic!("File notification channel closed"),
                 }
-                Err(TryRecvError::Empty) => None,
-                Err(TryRecvError::Disconnected) => panic!("File notification channel closed"),
             })
         }
         Some(Cmd::Build) => {
@@ -144,6 +363,47 @@ pub fn run(opts: Opts) {
                 .status()
                 .is_ok());
         }
+        Some(Cmd::Headless { ticks, screenshot }) => {
+            if run_cargo(&opts.manifest_path, opts.build_path, "build", None) {
+                println!("Running project headless for {} ticks", ticks);
+                let engine = Options {
+                    asset_paths: asset_paths(&opts.manifest_path, &[]),
+                    ..Options::default()
+                };
+                let mut game = Headless::<Wasm>::load_with(engine, &read_wasm())
+                    .expect("Loading the runtime failed");
+                if screenshot.is_some() {
+                    game = game.render(640, 480);
+                }
+                game.run(ticks).expect("Game crashed");
+
+                let frames = 0..game.ticks();
+                let draws: usize = frames.clone().map(|f| game.draws(f).count()).sum();
+                let plays: usize = frames.map(|f| game.plays(f).count()).sum();
+                println!(
+                    "Ran {} ticks, drew {} sprites and played {} sounds",
+                    game.ticks(),
+                    draws,
+                    plays
+                );
+
+                if let (Some(path), Some(image)) = (screenshot, game.image()) {
+                    image.save(&path).expect("Failed to save screenshot");
+                    println!("Saved the last tick to {}", path.display());
+                }
+            }
+        }
+        Some(Cmd::Pack {
+            ref dir,
+            ref out,
+            compress,
+        }) => {
+            let dir = dir
+                .clone()
+                .unwrap_or_else(|| opts.manifest_path.with_file_name("assets"));
+            println!("Packing {} into {}", dir.display(), out.display());
+            vg_native::build_pack(&dir, out, compress).expect("Failed to pack assets");
+        }
         Some(Cmd::Clean) => {
             println!("Cleaning project");
             run_cargo(opts.manifest_path, opts.build_path, "clean", None);
@@ -1,65 +1,124 @@
 use std::{
+    collections::HashMap,
     fs::File as StdFile,
-    io::{Read, Seek, SeekFrom},
-    path::PathBuf,
-    sync::Arc,
+    io::{self, Read, Seek, SeekFrom, Take},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    time::Duration,
 };
 
 use bytes::BytesMut;
 use dashmap::DashMap;
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
 use tokio::{
     fs::{canonicalize, File},
     io::{AsyncRead, AsyncReadExt, AsyncSeekExt},
 };
-use tracing::{debug, trace, warn};
+use tracing::{debug, error, trace, warn};
+
+use crate::pack::{Entry, Pack};
+
+/// Bytes read ahead when an asset is first requested
+const PRELOAD: usize = 16 * 1024;
+
+/// Archive mounted automatically when it exists
+const DEFAULT_PACK: &str = "assets.vgpak";
+
+/// Where embedded assets appear to live, so they have paths like the rest
+const EMBEDDED: &str = "<embedded>";
+
+/// Assets compiled into the executable, by name
+pub type Embedded = &'static [(&'static str, &'static [u8])];
+
+/// Somewhere assets are looked up from
+enum Mount {
+    Dir(PathBuf),
+    Pack(Pack),
+    Embedded(HashMap<&'static str, &'static [u8]>),
+}
 
 pub struct Assets {
-    paths: Vec<PathBuf>,
+    mounts: Vec<Mount>,
     cache: DashMap<PathBuf, Arc<Cache>>,
+    /// Assets that failed to load, so each is only looked for and logged once
+    failed: DashMap<String, String>,
+    // keeps the watcher alive as long as we are
+    _watcher: Option<RecommendedWatcher>,
+    changes: Receiver<DebouncedEvent>,
 }
 
 pub struct Cache {
     pub len: usize,
+    /// Identifies the asset, inside the archive path for packed assets
     pub path: PathBuf,
     pub first: BytesMut,
+    /// File the rest of the asset is read from, and where in it the asset
+    /// starts. Assets that are fully preloaded have none
+    source: Option<(PathBuf, u64)>,
 }
 
 impl Cache {
-    pub async fn load_all(&self) -> Vec<u8> {
+    /// Read the whole asset, failing if its file went away or got shorter
+    /// since it was first looked up
+    pub async fn load_all(&self) -> io::Result<Vec<u8>> {
         puffin::profile_function!();
         let mut buf = self.first.to_vec();
 
-        if buf.len() >= self.len {
-            return buf;
-        }
-
-        let mut file = File::open(&self.path).await.unwrap();
+        let (path, start) = match &self.source {
+            Some(source) if buf.len() < self.len => source,
+            _ => return Ok(buf),
+        };
+        let mut file = File::open(path).await?;
 
         // skip first n bytes because those are already pre-loaded
-        file.seek(SeekFrom::Start(buf.len() as u64)).await.unwrap();
-        file.read_to_end(&mut buf).await.unwrap();
+        let rest = (self.len - buf.len()) as u64;
+        file.seek(SeekFrom::Start(start + buf.len() as u64)).await?;
+        file.take(rest).read_to_end(&mut buf).await?;
+
+        if buf.len() < self.len {
+            return Err(truncated());
+        }
 
-        buf
+        Ok(buf)
     }
 
-    pub async fn start_read(&self) -> CacheRead {
+    /// Start reading the asset from the beginning, for streaming it
+    pub async fn start_read(&self) -> io::Result<CacheRead> {
         let buf = self.first.to_vec();
-        let mut file = StdFile::open(&self.path).unwrap();
+        let rest = (self.len - buf.len()) as u64;
 
-        // skip first n bytes because those are already pre-loaded
-        file.seek(SeekFrom::Start(buf.len() as u64)).unwrap();
+        let file = match &self.source {
+            Some((path, start)) => {
+                let mut file = StdFile::open(path)?;
 
-        CacheRead {
+                // skip first n bytes because those are already pre-loaded
+                file.seek(SeekFrom::Start(start + buf.len() as u64))?;
+                Some(file.take(rest))
+            }
+            None => None,
+        };
+
+        Ok(CacheRead {
             buf,
             file,
             cursor: 0,
-        }
+        })
     }
 }
 
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "file got shorter while in use",
+    )
+}
+
 pub struct CacheRead {
     buf: Vec<u8>,
-    file: StdFile,
+    file: Option<Take<StdFile>>,
     cursor: usize,
 }
 
@@ -77,29 +136,121 @@ impl Read for CacheRead {
             self.buf.clear();
             self.buf.shrink_to_fit();
 
-            self.file.read(buf)
+            match &mut self.file {
+                Some(file) => file.read(buf),
+                None => Ok(0),
+            }
         }
     }
 }
 
 impl Assets {
-    pub fn new() -> Assets {
+    /// Search for assets in `paths`, earlier paths first, or `assets/` when
+    /// none are given. Embedded assets are searched before any of them
+    pub fn new(paths: &[PathBuf], embedded: Embedded) -> Assets {
+        let (tx, changes) = channel();
         let mut a = Assets {
-            paths: vec!["assets/".into()],
+            mounts: vec![],
             cache: DashMap::new(),
+            failed: DashMap::new(),
+            _watcher: None,
+            changes,
         };
 
-        a.fix();
+        if !embedded.is_empty() {
+            debug!("Mounting {} embedded assets", embedded.len());
+            let table = embedded.iter().copied().collect();
+            a.mounts.push(Mount::Embedded(table));
+        }
 
-        debug!("Asset search paths: {:?}", a.paths);
+        if paths.is_empty() {
+            a.mount("assets/");
+        }
+        for path in paths {
+            a.mount(path);
+        }
+
+        // shipped games carry their assets in an archive
+        if Path::new(DEFAULT_PACK).exists() {
+            a.mount(DEFAULT_PACK);
+        }
+
+        match watcher(tx, Duration::from_millis(200)) {
+            Ok(mut watcher) => {
+                for path in a.dirs() {
+                    if let Err(err) = watcher.watch(path, RecursiveMode::Recursive) {
+                        warn!("Failed to watch {} for changes: {}", path.display(), err);
+                    }
+                }
+                a._watcher = Some(watcher);
+            }
+            Err(err) => warn!("Asset hot reloading unavailable: {}", err),
+        }
 
         a
     }
 
+    /// Assets changed on disk since the last call. Their cached data is thrown
+    /// out, so the next `get` reads them again
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        while let Ok(event) = self.changes.try_recv() {
+            match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Remove(path)
+                | DebouncedEvent::Rename(_, path) => changed.push(path),
+                DebouncedEvent::Error(err, _) => warn!("Asset watcher failed: {}", err),
+                _ => (),
+            }
+        }
+
+        for path in &changed {
+            if self.cache.remove(path).is_some() {
+                debug!("Asset changed: {}", path.display());
+            }
+        }
+
+        // a missing asset might have just been added
+        if !changed.is_empty() {
+            self.failed.clear();
+        }
+
+        changed
+    }
+
+    /// Add a directory or `.vgpak` archive to search for assets, after every
+    /// search path added before it
+    pub fn mount(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        debug!("Mounting asset search path {}", path.display());
+
+        if path.extension().map_or(false, |ext| ext == "vgpak") {
+            match Pack::open(&path) {
+                Ok(pack) => self.mounts.push(Mount::Pack(pack)),
+                Err(err) => warn!("Failed to mount {}: {}", path.display(), err),
+            }
+        } else {
+            self.mounts.push(Mount::Dir(path));
+            self.fix();
+        }
+    }
+
+    fn dirs(&self) -> impl Iterator<Item = &PathBuf> {
+        self.mounts.iter().filter_map(|mount| match mount {
+            Mount::Dir(path) => Some(path),
+            _ => None,
+        })
+    }
+
     fn fix(&mut self) {
         puffin::profile_function!();
 
-        for path in &mut self.paths {
+        let dirs = self.mounts.iter_mut().filter_map(|mount| match mount {
+            Mount::Dir(path) => Some(path),
+            _ => None,
+        });
+        for path in dirs {
             match path.canonicalize() {
                 Ok(p) => *path = p,
                 Err(err) => warn!(
@@ -111,45 +262,130 @@ impl Assets {
         }
     }
 
-    pub async fn get(&self, asset: &str) -> Arc<Cache> {
+    /// Look an asset up, or `None` if it can't be loaded. Failures are logged
+    /// once per asset
+    pub async fn get(&self, asset: &str) -> Option<Arc<Cache>> {
         puffin::profile_function!();
+
+        if self.failed.contains_key(asset) {
+            return None;
+        }
+
+        match self.find(asset).await {
+            Ok(cache) => Some(cache),
+            Err(err) => {
+                self.fail(asset, err);
+                None
+            }
+        }
+    }
+
+    /// Remember that an asset is broken. The game is told about it on the
+    /// tick after one that used it
+    pub fn fail(&self, asset: &str, reason: String) {
+        if self.failed.contains_key(asset) {
+            return;
+        }
+
+        error!("Failed to load asset {}: {}", asset, reason);
+        self.failed.insert(asset.into(), reason);
+    }
+
+    /// Why an asset failed to load, if it has
+    pub fn failure(&self, asset: &str) -> Option<String> {
+        self.failed.get(asset).map(|reason| reason.clone())
+    }
+
+    /// Look an asset up without reporting it when it can't be found
+    pub async fn find(&self, asset: &str) -> Result<Arc<Cache>, String> {
         trace!("Fetching asset: {}", asset);
 
         // Look for the asset in any of the search paths
-        let mut found = None;
-        for source in &self.paths {
-            let path = source.join(asset);
+        for mount in &self.mounts {
+            let path = match mount {
+                Mount::Dir(dir) => dir.join(asset),
+                Mount::Pack(pack) => pack.path.join(asset),
+                Mount::Embedded(_) => Path::new(EMBEDDED).join(asset),
+            };
 
             // Already cached, just use that one
             if let Some(cache) = self.cache.get(&path) {
-                return Arc::clone(&*cache);
+                return Ok(Arc::clone(&*cache));
             }
 
-            if let Ok(path) = canonicalize(path).await {
-                found = Some(path);
-                break;
-            }
+            let cache = match mount {
+                Mount::Dir(_) => match canonicalize(&path).await {
+                    Ok(path) => load_file(path).await,
+                    Err(_) => continue,
+                },
+                Mount::Pack(pack) => match pack.entry(asset) {
+                    Some(entry) => load_packed(path.clone(), pack, entry.clone()).await,
+                    None => continue,
+                },
+                Mount::Embedded(table) => match table.get(asset) {
+                    Some(bytes) => Ok(Cache {
+                        len: bytes.len(),
+                        first: BytesMut::from(*bytes),
+                        source: None,
+                        path: path.clone(),
+                    }),
+                    None => continue,
+                },
+            };
+
+            trace!("Loading asset: {}", asset);
+            let cache = Arc::new(cache.map_err(|e| format!("{}: {}", path.display(), e))?);
+            self.cache.insert(cache.path.clone(), Arc::clone(&cache));
+            return Ok(cache);
         }
 
-        let path = found.expect("Asset not found in any search path");
-        trace!("Loading asset: {}", asset);
+        Err("not found in any search path".into())
+    }
+}
+
+async fn load_file(path: PathBuf) -> io::Result<Cache> {
+    let mut buf = BytesMut::with_capacity(PRELOAD);
+    let mut file = File::open(&path).await?;
+    let meta = file.metadata().await?;
 
-        let preload = 16 * 1024; // Preload first 16kb of data
-        let mut buf = BytesMut::with_capacity(preload);
-        let mut file = File::open(&path).await.unwrap();
-        let meta = file.metadata().await.unwrap();
+    file.read_buf(&mut buf).await?;
 
-        file.read_buf(&mut buf).await.unwrap();
+    Ok(Cache {
+        first: buf,
+        len: meta.len() as _,
+        source: Some((path.clone(), 0)),
+        path,
+    })
+}
+
+async fn load_packed(path: PathBuf, pack: &Pack, entry: Entry) -> io::Result<Cache> {
+    let mut file = File::open(&pack.path).await?;
+    file.seek(SeekFrom::Start(entry.offset)).await?;
 
-        self.cache.insert(
-            path.clone(),
-            Arc::new(Cache {
-                first: buf,
-                len: meta.len() as _,
-                path: path.clone(),
-            }),
-        );
+    // compressed assets cannot be streamed, inflate them whole
+    if entry.compressed {
+        let mut bytes = vec![];
+        file.take(entry.len).read_to_end(&mut bytes).await?;
+        let bytes = miniz_oxide::inflate::decompress_to_vec(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("corrupt: {:?}", e)))?;
 
-        Arc::clone(&*self.cache.get(&path).unwrap())
+        return Ok(Cache {
+            len: bytes.len(),
+            first: BytesMut::from(&bytes[..]),
+            source: None,
+            path,
+        });
     }
+
+    let mut buf = BytesMut::with_capacity(PRELOAD);
+    file.take(entry.len.min(PRELOAD as u64))
+        .read_buf(&mut buf)
+        .await?;
+
+    Ok(Cache {
+        first: buf,
+        len: entry.len as _,
+        source: Some((pack.path.clone(), entry.offset)),
+        path,
+    })
 }
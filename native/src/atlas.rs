@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use image::{ImageFormat, RgbaImage};
+use tracing::{debug, info};
+use vg_types::{DeJson, SerJson};
+
+use crate::{assets::Assets, runtime::Error};
+
+/// Manifest of the atlas, found through the regular asset search paths
+pub const MANIFEST: &str = "vg-atlas.json";
+
+const PAGE_SIZE: u32 = 2048;
+/// Bigger images gain little from sharing a texture, and would fill pages fast
+const MAX_SPRITE: u32 = 512;
+/// Edge pixels repeated around every sprite, so filtering does not bleed in
+/// its neighbours
+const PADDING: u32 = 2;
+
+/// Where a single sprite lies on the atlas, in pixels
+#[derive(SerJson, DeJson, Debug, Clone, Copy)]
+pub struct Region {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(SerJson, DeJson, Debug, Clone)]
+pub struct Manifest {
+    /// Search paths the atlas was built from, it is stale once they change
+    pub sources: Vec<String>,
+    /// Every image found in them, packed or not, to notice added and deleted
+    /// ones
+    pub images: Vec<String>,
+    /// Page images, next to the manifest
+    pub pages: Vec<String>,
+    /// Regions by the asset name the game draws them with
+    pub regions: HashMap<String, Region>,
+}
+
+/// Pack the images found in `dirs` onto atlas pages in `out`, earlier
+/// directories taking priority like they do for assets. Nothing is done when
+/// the atlas is newer than every image and no image was added or removed
+pub fn build_atlas(dirs: &[PathBuf], out: &Path) -> Result<(), Error> {
+    puffin::profile_function!();
+
+    let sources: Vec<String> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+
+    let mut images: HashMap<String, PathBuf> = HashMap::new();
+    for dir in dirs.iter().rev().filter(|dir| dir.is_dir()) {
+        images.extend(
+            vg_types::asset_files(dir)?
+                .into_iter()
+                .filter(|(_, path)| ImageFormat::from_path(path).is_ok()),
+        );
+    }
+
+    let mut names: Vec<String> = images.keys().cloned().collect();
+    names.sort();
+
+    if up_to_date(out, &sources, &names, images.values()) {
+        debug!("Texture atlas is up to date");
+        return Ok(());
+    }
+
+    let mut sprites = vec![];
+    for (name, path) in images {
+        let img = image::open(&path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+            .to_rgba8();
+        let (width, height) = img.dimensions();
+        if width > 0 && height > 0 && width <= MAX_SPRITE && height <= MAX_SPRITE {
+            sprites.push((name, img));
+        }
+    }
+
+    // tallest first keeps the shelves tight
+    sprites
+        .sort_by(|(a_name, a), (b_name, b)| b.height().cmp(&a.height()).then(a_name.cmp(b_name)));
+
+    let mut pages = vec![RgbaImage::new(PAGE_SIZE, PAGE_SIZE)];
+    let mut regions = HashMap::new();
+    let (mut x, mut y, mut shelf) = (0, 0, 0);
+    for (name, img) in &sprites {
+        let (width, height) = (img.width() + PADDING * 2, img.height() + PADDING * 2);
+
+        if x + width > PAGE_SIZE {
+            x = 0;
+            y += shelf;
+            shelf = 0;
+        }
+        if y + height > PAGE_SIZE {
+            pages.push(RgbaImage::new(PAGE_SIZE, PAGE_SIZE));
+            x = 0;
+            y = 0;
+            shelf = 0;
+        }
+
+        let page = pages.len() - 1;
+        extrude(&mut pages[page], img, x, y);
+        regions.insert(
+            name.clone(),
+            Region {
+                page,
+                x: x + PADDING,
+                y: y + PADDING,
+                width: img.width(),
+                height: img.height(),
+            },
+        );
+
+        x += width;
+        shelf = shelf.max(height);
+    }
+
+    std::fs::create_dir_all(out)?;
+
+    let mut page_names = vec![];
+    for (i, page) in pages.iter().enumerate() {
+        let name = format!("vg-atlas-{}.png", i);
+        page.save(out.join(&name))?;
+        page_names.push(name);
+    }
+
+    let manifest = Manifest {
+        sources,
+        images: names,
+        pages: page_names,
+        regions,
+    };
+    std::fs::write(out.join(MANIFEST), manifest.serialize_json())?;
+
+    info!(
+        "Packed {} sprites onto {} atlas pages in {}",
+        sprites.len(),
+        pages.len(),
+        out.display()
+    );
+
+    Ok(())
+}
+
+/// Copy an image onto a page with its edges repeated into the padding around
+/// it. Empty images have no edges and leave the page as is
+fn extrude(page: &mut RgbaImage, img: &RgbaImage, x: u32, y: u32) {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    for py in 0..height + PADDING * 2 {
+        for px in 0..width + PADDING * 2 {
+            let sx = px.saturating_sub(PADDING).min(width - 1);
+            let sy = py.saturating_sub(PADDING).min(height - 1);
+            page.put_pixel(x + px, y + py, *img.get_pixel(sx, sy));
+        }
+    }
+}
+
+fn up_to_date<'a>(
+    out: &Path,
+    sources: &[String],
+    names: &[String],
+    images: impl Iterator<Item = &'a PathBuf>,
+) -> bool {
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+
+    let built = match modified(&out.join(MANIFEST)) {
+        Some(built) => built,
+        None => return false,
+    };
+
+    // deleted images would keep being drawn from the atlas, and added ones
+    // can carry an old modification time
+    let same_images = std::fs::read_to_string(out.join(MANIFEST))
+        .ok()
+        .and_then(|json| Manifest::deserialize_json(&json).ok())
+        .map_or(false, |manifest| {
+            manifest.sources == sources && manifest.images == names
+        });
+
+    same_images
+        && images
+            .map(|path| modified(path).unwrap_or_else(SystemTime::now))
+            .all(|time| time <= built)
+}
+
+/// Read the atlas manifest and decode its pages, if the game has an atlas
+pub async fn load(assets: &Assets) -> Result<Option<(Manifest, Vec<RgbaImage>)>, Error> {
+    puffin::profile_function!();
+
+    // the atlas is optional, so it not being there is no failure
+    let manifest = match assets.find(MANIFEST).await {
+        Ok(cache) => cache.load_all().await?,
+        Err(_) => return Ok(None),
+    };
+    let manifest = Manifest::deserialize_json(&String::from_utf8_lossy(&manifest))
+        .map_err(|e| format!("Corrupt texture atlas manifest: {:?}", e))?;
+
+    let mut pages = vec![];
+    for page in &manifest.pages {
+        let bytes = assets.find(page).await?.load_all().await?;
+        pages.push(image::load_from_memory(&bytes)?.to_rgba8());
+    }
+
+    debug!(
+        "Loaded texture atlas of {} sprites on {} pages",
+        manifest.regions.len(),
+        pages.len()
+    );
+
+    Ok(Some((manifest, pages)))
+}
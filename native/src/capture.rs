@@ -0,0 +1,79 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use image::RgbaImage;
+use tracing::{debug, info, warn};
+
+use crate::runtime::Error;
+
+const SCREENSHOTS: &str = "captures/";
+
+/// Saves presented frames, as read back from the GPU, to PNG
+pub struct Capture {
+    /// Save the next presented frame as a screenshot
+    pub screenshot: bool,
+    /// Directory every presented frame is saved in
+    frames: Option<PathBuf>,
+    frame: usize,
+}
+
+impl Capture {
+    pub fn new(frames: Option<PathBuf>) -> Capture {
+        if let Some(dir) = &frames {
+            info!("Capturing every frame to {}", dir.display());
+        }
+
+        Capture {
+            screenshot: false,
+            frames,
+            frame: 0,
+        }
+    }
+
+    /// Whether the next frame needs to be read back for saving
+    pub fn wanted(&self) -> bool {
+        self.screenshot || self.frames.is_some()
+    }
+
+    /// Save a presented frame, if anything asked for it
+    pub fn present(&mut self, frame: &RgbaImage) {
+        puffin::profile_function!();
+
+        if self.screenshot {
+            self.screenshot = false;
+
+            let stamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let path = Path::new(SCREENSHOTS).join(format!("screenshot-{}.png", stamp));
+            match save(frame, &path) {
+                Ok(()) => info!("Saved screenshot to {}", path.display()),
+                Err(e) => warn!("Failed to save screenshot: {}", e),
+            }
+        }
+
+        if let Some(dir) = &self.frames {
+            let path = dir.join(format!("frame-{:06}.png", self.frame));
+            self.frame += 1;
+
+            if let Err(e) = save(frame, &path) {
+                warn!("Failed to capture frame, stopping: {}", e);
+                self.frames = None;
+            }
+        }
+    }
+}
+
+fn save(frame: &RgbaImage, path: &Path) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    frame.save(path)?;
+    debug!("Captured {}", path.display());
+
+    Ok(())
+}
@@ -31,6 +31,9 @@ pub struct DebugData {
     pub last_draw: Instant,
     pub tick_time: Duration,
     pub force_smooth: bool,
+    pub crash: Option<String>,
+    pub overruns: usize,
+    pub dropped: Duration,
 }
 
 impl DebugData {
@@ -59,6 +62,9 @@ impl DebugData {
             last_draw: Instant::now(),
             tick_time: Duration::from_millis(1),
             force_smooth: false,
+            crash: None,
+            overruns: 0,
+            dropped: Duration::ZERO,
         }
     }
 
@@ -85,6 +91,10 @@ impl epi::App for DebugData {
                 1.0 / self.tick_time.as_secs_f32(),
                 self.tick_time,
             ));
+            ui.label(format!(
+                "Tick overruns: {} / {:.2?} dropped",
+                self.overruns, self.dropped,
+            ));
 
             ui.spacing();
 
@@ -112,6 +122,13 @@ impl epi::App for DebugData {
             }
         });
 
+        if let Some(crash) = &self.crash {
+            egui::Window::new("Crashed").show(ctx, |ui| {
+                ui.label("The game crashed, waiting for a new build");
+                ui.monospace(crash);
+            });
+        }
+
         if self.profiler {
             puffin_egui::profiler_window(ctx);
         }
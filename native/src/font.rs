@@ -0,0 +1,153 @@
+use image::{Rgba, RgbaImage};
+
+/// Characters per line, longer lines are wrapped
+const COLUMNS: usize = 80;
+/// Lines written at most, the rest are left out
+const LINES: usize = 40;
+/// Pixels from one character to the next, and from one line to the next
+const ADVANCE: u32 = 4;
+const LINE_HEIGHT: u32 = 6;
+/// Empty pixels around the text
+const PADDING: u32 = 4;
+
+/// Image of `paragraphs` written in a tiny pixel font on black, each in its
+/// own color. Meant for messages from the engine, when there are no assets
+/// or debug UI to show them with
+pub fn text(paragraphs: &[(&str, [u8; 3])]) -> RgbaImage {
+    let mut lines = vec![];
+    for (text, color) in paragraphs {
+        for line in text.lines() {
+            let chars: Vec<char> = line.chars().collect();
+            if chars.is_empty() {
+                lines.push((vec![], *color));
+            }
+            for chunk in chars.chunks(COLUMNS) {
+                lines.push((chunk.to_vec(), *color));
+            }
+        }
+    }
+    lines.truncate(LINES);
+
+    let columns = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    let width = columns as u32 * ADVANCE + 2 * PADDING;
+    let height = lines.len() as u32 * LINE_HEIGHT + 2 * PADDING;
+    let mut image = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+
+    for (row, (line, [r, g, b])) in lines.iter().enumerate() {
+        for (column, c) in line.iter().enumerate() {
+            let left = PADDING + column as u32 * ADVANCE;
+            let top = PADDING + row as u32 * LINE_HEIGHT;
+
+            for (y, bits) in glyph(*c).iter().enumerate() {
+                for x in 0..3 {
+                    if bits >> (2 - x) & 1 == 1 {
+                        image.put_pixel(left + x, top + y as u32, Rgba([*r, *g, *b, 255]));
+                    }
+                }
+            }
+        }
+    }
+
+    image
+}
+
+/// Rows of a 3 by 5 pixel character from the top, the highest bit leftmost.
+/// Letters are all capitals and unknown characters are filled boxes
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' | '\t' => [0, 0, 0, 0, 0],
+        '0' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 1, 1, 1],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        'A' => [2, 5, 7, 5, 5],
+        'B' => [6, 5, 6, 5, 6],
+        'C' => [3, 4, 4, 4, 3],
+        'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7],
+        'F' => [7, 4, 6, 4, 4],
+        'G' => [3, 4, 5, 5, 3],
+        'H' => [5, 5, 7, 5, 5],
+        'I' => [7, 2, 2, 2, 7],
+        'J' => [1, 1, 1, 5, 2],
+        'K' => [5, 5, 6, 5, 5],
+        'L' => [4, 4, 4, 4, 7],
+        'M' => [5, 7, 7, 5, 5],
+        'N' => [6, 5, 5, 5, 5],
+        'O' => [2, 5, 5, 5, 2],
+        'P' => [6, 5, 6, 4, 4],
+        'Q' => [2, 5, 5, 7, 3],
+        'R' => [6, 5, 6, 5, 5],
+        'S' => [3, 4, 2, 1, 6],
+        'T' => [7, 2, 2, 2, 2],
+        'U' => [5, 5, 5, 5, 7],
+        'V' => [5, 5, 5, 5, 2],
+        'W' => [5, 5, 7, 7, 5],
+        'X' => [5, 5, 2, 5, 5],
+        'Y' => [5, 5, 2, 2, 2],
+        'Z' => [7, 1, 2, 4, 7],
+        '.' => [0, 0, 0, 0, 2],
+        ',' => [0, 0, 0, 2, 4],
+        ':' => [0, 2, 0, 2, 0],
+        ';' => [0, 2, 0, 2, 4],
+        '!' => [2, 2, 2, 0, 2],
+        '?' => [7, 1, 2, 0, 2],
+        '\'' => [2, 2, 0, 0, 0],
+        '"' => [5, 5, 0, 0, 0],
+        '`' => [4, 2, 0, 0, 0],
+        '^' => [2, 5, 0, 0, 0],
+        '~' => [0, 0, 3, 6, 0],
+        '-' => [0, 0, 7, 0, 0],
+        '+' => [0, 2, 7, 2, 0],
+        '*' => [0, 5, 2, 5, 0],
+        '=' => [0, 7, 0, 7, 0],
+        '_' => [0, 0, 0, 0, 7],
+        '/' => [1, 1, 2, 4, 4],
+        '\\' => [4, 4, 2, 1, 1],
+        '|' => [2, 2, 2, 2, 2],
+        '(' => [1, 2, 2, 2, 1],
+        ')' => [4, 2, 2, 2, 4],
+        '[' => [3, 2, 2, 2, 3],
+        ']' => [6, 2, 2, 2, 6],
+        '{' => [3, 2, 6, 2, 3],
+        '}' => [6, 2, 3, 2, 6],
+        '<' => [1, 2, 4, 2, 1],
+        '>' => [4, 2, 1, 2, 4],
+        '#' => [5, 7, 5, 7, 5],
+        '%' => [5, 1, 2, 4, 5],
+        '&' => [2, 5, 2, 5, 3],
+        '@' => [2, 5, 7, 4, 3],
+        '$' => [3, 6, 2, 3, 6],
+        _ => [7, 7, 7, 7, 7],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_long_lines() {
+        let long = "x".repeat(COLUMNS + 1);
+        let image = text(&[("Hi", [255, 0, 255]), (&long, [255; 3])]);
+        assert_eq!(image.width(), COLUMNS as u32 * ADVANCE + 2 * PADDING);
+        assert_eq!(image.height(), 3 * LINE_HEIGHT + 2 * PADDING);
+
+        // top left of the H, then the gap after it
+        assert_eq!(image.get_pixel(PADDING, PADDING).0, [255, 0, 255, 255]);
+        assert_eq!(image.get_pixel(PADDING + 3, PADDING).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn leaves_out_lines_past_the_limit() {
+        let many = "a\n".repeat(LINES * 2);
+        let image = text(&[(&many, [255; 3])]);
+        assert_eq!(image.height(), LINES as u32 * LINE_HEIGHT + 2 * PADDING);
+    }
+}
@@ -0,0 +1,810 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::LocalBoxFuture;
+use tracing::{debug, error, info, trace, warn};
+use vg_types::{
+    Call, Config, ConfigChange, DrawCall, PlayCall, Presentation, Response, TilemapCall, Transform,
+};
+
+use crate::{
+    assets::Assets,
+    interpolate::History,
+    net::Session,
+    preload::{Decoded, Preloader},
+    replay::{Player, Recorder},
+    runtime::{self, Runtime},
+    tilemap::{self, Map},
+    Options,
+};
+
+/// Everything a single frame draws and plays
+#[derive(Default)]
+pub struct Frame {
+    pub draws: Vec<DrawCall>,
+    pub plays: Vec<PlayCall>,
+    pub tilemaps: Vec<(Arc<Map>, Transform)>,
+    /// The frame is complete and should be shown
+    pub present: bool,
+    /// Save the frame once it is shown
+    pub screenshot: bool,
+}
+
+/// Where the frames of a game end up, a window and speakers or nowhere at all
+pub trait Output {
+    /// Draw and play a frame. Assets that turn out to be broken are failed in
+    /// `assets`
+    fn frame<'a>(&'a mut self, assets: &'a Assets, frame: Frame) -> LocalBoxFuture<'a, ()>;
+
+    /// Every call a fixed tick made, before any of them are carried out
+    fn tick(&mut self, _calls: &[Call]) {}
+
+    /// Decode the sprites a fixed tick drew before any frame shows them,
+    /// failing broken ones in `assets`
+    fn prepare<'a>(
+        &'a mut self,
+        _assets: &'a Assets,
+        _draws: &'a [DrawCall],
+    ) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+
+    /// Whether an asset is drawn from the texture atlas
+    fn in_atlas(&self, _asset: &str) -> bool {
+        false
+    }
+
+    /// Whether a file is decoded and uploaded already
+    fn has_decoded(&self, _path: &Path) -> bool {
+        false
+    }
+
+    /// Take an asset decoded ahead of time
+    fn preloaded(&mut self, _path: PathBuf, _decoded: Decoded) {}
+
+    /// Throw out everything decoded from files that changed
+    fn forget(&mut self, _paths: &[PathBuf]) {}
+
+    /// Throw out the meshes of a tile map read from `path`
+    fn forget_tilemap(&mut self, _path: &Path) {}
+
+    /// Apply the parts of a new configuration that concern the output
+    fn configure(&mut self, _old: &Config, _new: &Config) {}
+
+    fn print(&mut self, msg: String) {
+        info!("{}", msg);
+    }
+
+    /// The game crashed with a message, or a new build recovered from it
+    fn crashed(&mut self, _message: Option<&str>) {}
+
+    /// Ticks were dropped to catch up, slowing the game down by `dropped`
+    fn overrun(&mut self, _dropped: Duration) {}
+
+    /// Show interpolated frames even if the game asked for speculative ones,
+    /// checked before every update
+    fn smooth(&self) -> bool {
+        false
+    }
+}
+
+/// A running game, its runtimes and when they tick, and every call that is
+/// not drawing or playing. Shared by the windowed engine and headless runs
+pub struct Game<RT> {
+    opts: Options,
+    assets: Assets,
+    config: Config,
+    tick_time: Duration,
+    frame_cap: Option<Duration>,
+    /// Runs the fixed ticks, the actual state of the game
+    tick_runtime: Option<RT>,
+    /// Copy of the tick runtime running ahead of it for speculative frames
+    frame_runtime: Option<RT>,
+    session: Option<Session<RT>>,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    crashed: Option<String>,
+    exited: bool,
+    history: History,
+    preloader: Preloader,
+    /// Tile maps read so far, by asset name
+    maps: HashMap<String, Arc<Map>>,
+    /// Answers to calls, sent to the game before its next tick
+    replies: Vec<Response>,
+    /// Assets the last fixed tick used, checked for failures before the next
+    referenced: Vec<String>,
+    /// Failed assets the game has been told about
+    reported: HashSet<String>,
+    next_tick: Instant,
+    last_frame: Instant,
+    /// Whether the last fixed tick has been shown yet
+    shown_tick: bool,
+    /// Interpolated frames forced by the output
+    smooth: bool,
+}
+
+impl<RT: Runtime> Game<RT> {
+    pub fn new(opts: Options) -> Game<RT> {
+        let config = Config::default();
+
+        Game {
+            assets: Assets::new(&opts.asset_paths, opts.embedded_assets),
+            opts,
+            tick_time: Duration::from_secs_f64(1.0 / config.tick_rate),
+            frame_cap: None,
+            config,
+            tick_runtime: None,
+            frame_runtime: None,
+            session: None,
+            recorder: None,
+            player: None,
+            crashed: None,
+            exited: false,
+            history: Default::default(),
+            preloader: Preloader::new(),
+            maps: HashMap::new(),
+            replies: vec![],
+            referenced: vec![],
+            reported: HashSet::new(),
+            next_tick: Instant::now(),
+            last_frame: Instant::now(),
+            shown_tick: false,
+            smooth: false,
+        }
+    }
+
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    pub fn runtime(&self) -> Option<&RT> {
+        self.tick_runtime.as_ref()
+    }
+
+    pub fn tick_time(&self) -> Duration {
+        self.tick_time
+    }
+
+    pub fn crashed(&self) -> bool {
+        self.crashed.is_some()
+    }
+
+    /// Whether the game asked to exit
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Run a new build of the game, keeping the state of the previous one
+    /// where possible
+    pub fn load(&mut self, code: &[u8], out: &mut impl Output) -> Result<(), runtime::Error> {
+        // rolling back to the inputs of peers takes a copy of every tick
+        if self.opts.net.is_some() && !RT::can_duplicate() {
+            return Err("Online sessions need a runtime that can be duplicated".into());
+        }
+
+        let mut rt = RT::load(code)?;
+        self.frame_runtime = None;
+
+        // a crashed game has nothing worth keeping
+        let crashed = self.recover(out);
+        self.reported.clear();
+
+        // keep playing from where the previous build left off, unless
+        // peers of an online session would restart from the beginning
+        if let (Some(old), None, false) = (&mut self.tick_runtime, &self.opts.net, crashed) {
+            match rt.reload_from(old) {
+                Ok(true) => info!("Reloaded game, state kept"),
+                Ok(false) => info!("Reloaded game from the start"),
+                Err(e) => {
+                    warn!("Failed to carry game state over, restarting: {}", e);
+                    rt = RT::load(code)?;
+                }
+            }
+        }
+
+        if let Some(net) = &self.opts.net {
+            // release the socket of the previous session first
+            drop(self.session.take());
+            let session = Session::new(net.clone())?;
+            rt.send(session.greeting())?;
+            self.session = Some(session);
+            self.tick_runtime = Some(rt);
+            return Ok(());
+        }
+
+        self.tick_runtime = Some(rt);
+
+        // recordings always start from a freshly loaded game
+        if let Some(path) = &self.opts.record {
+            self.recorder = Some(Recorder::create(path, runtime::code_hash(code))?);
+        }
+        if let Some(path) = &self.opts.replay {
+            self.player = Some(Player::open(path, runtime::code_hash(code))?);
+        }
+
+        Ok(())
+    }
+
+    /// A key was pressed or released on the keyboard
+    pub fn key(&mut self, out: &mut impl Output, key: vg_types::Key, down: bool) {
+        if let Some(session) = &mut self.session {
+            return session.set_key(key, down);
+        }
+
+        let response = if down {
+            Response::Down(key)
+        } else {
+            Response::Up(key)
+        };
+        if let Err(e) = self.send(response) {
+            self.crash(out, e);
+        }
+    }
+
+    /// Send live input to the tick runtime, unless a replay is driving it
+    pub fn send(&mut self, response: Response) -> Result<(), runtime::Error> {
+        match &mut self.tick_runtime {
+            Some(rt) => send_live(&mut self.recorder, &self.player, rt, response),
+            None => Ok(()),
+        }
+    }
+
+    /// Run whatever is due by `now`, fixed ticks to catch up with it or a
+    /// frame in between them
+    pub async fn update(&mut self, out: &mut impl Output, now: Instant) {
+        if self.tick_runtime.is_none() || self.crashed.is_some() {
+            return;
+        }
+
+        self.poll(out);
+
+        if self.smooth != out.smooth() {
+            self.smooth = out.smooth();
+            self.history.clear();
+        }
+
+        // we should run fixed ticks, as many as it takes to catch up
+        if self.next_tick < now && self.shown_tick {
+            let behind = now - self.next_tick;
+            let due = (behind.as_secs_f64() / self.tick_time.as_secs_f64()) as u32 + 1;
+            let steps = due.min(self.config.max_catch_up.max(1));
+
+            for step in 0..steps {
+                // only the last of the catch up ticks is worth drawing
+                match self.step(out, step + 1 == steps).await {
+                    Ok(true) => (),
+                    Ok(false) => return,
+                    Err(e) => return self.crash(out, e),
+                }
+            }
+
+            // too far behind to ever catch up, let the simulation slow down
+            if due > steps {
+                let dropped = now.saturating_duration_since(self.next_tick);
+                warn!(
+                    "Tick overrun, dropped {} ticks ({:.2?})",
+                    due - steps,
+                    dropped
+                );
+                self.next_tick = now;
+                out.overrun(dropped);
+            }
+
+            // interpolated ticks are only shown by the frames after them
+            self.shown_tick = self.presentation() == Presentation::Speculative;
+        } else {
+            // still waiting for fixed tick, so draw render ticks
+            self.shown_tick = true;
+
+            // game has asked for a lower framerate
+            if let Some(cap) = self.frame_cap {
+                if now.saturating_duration_since(self.last_frame) < cap {
+                    return;
+                }
+            }
+
+            if self.presentation() == Presentation::Interpolated {
+                // how far we are from the last tick towards the next one
+                let since = (now + self.tick_time).saturating_duration_since(self.next_tick);
+                let alpha = since.as_secs_f32() / self.tick_time.as_secs_f32();
+
+                self.present_interpolated(out, alpha.min(1.0)).await;
+                self.last_frame = now;
+                return;
+            }
+
+            let mut rt = match self.frame_runtime.take() {
+                Some(rt) => rt,
+                None => match self.tick_runtime.as_mut().map(|rt| rt.duplicate()) {
+                    Some(Ok(rt)) => rt,
+                    Some(Err(e)) => return self.crash(out, e),
+                    None => return,
+                },
+            };
+
+            let result = self.run_till_present(out, &mut rt).await;
+
+            // Pass a frames worth of time. Non-determenistic, but its okay because we rollback each tick
+            let elapsed = now.saturating_duration_since(self.last_frame);
+            let result = result.and_then(|()| rt.send(Response::Time(elapsed.as_secs_f64())));
+            self.frame_runtime = Some(rt);
+
+            if let Err(e) = result {
+                return self.crash(out, e);
+            }
+            self.last_frame = now;
+        }
+    }
+
+    /// Pick up assets that changed on disk
+    pub fn poll(&mut self, out: &mut impl Output) {
+        let changed = self.assets.changed();
+        if !changed.is_empty() {
+            self.forget_assets(out, &changed);
+        }
+    }
+
+    /// Run a single fixed tick, showing it if `present` is set. False when an
+    /// online session has to wait for its peers instead
+    pub async fn step(
+        &mut self,
+        out: &mut impl Output,
+        present: bool,
+    ) -> Result<bool, runtime::Error> {
+        let rt = match &mut self.tick_runtime {
+            Some(rt) => rt,
+            None => return Ok(false),
+        };
+
+        // Answers to the calls of earlier ticks. Always sent right before the
+        // next one and recorded, so replays and rollbacks see them on the
+        // same tick
+        let preloaded = finish_preloads(&mut self.preloader, &self.assets, out);
+        let mut replies: Vec<Response> = preloaded.into_iter().map(Response::Preloaded).collect();
+        replies.append(&mut self.replies);
+
+        // let the game know about assets it used but can't have
+        for asset in std::mem::take(&mut self.referenced) {
+            if let Some(reason) = self.assets.failure(&asset) {
+                if self.reported.insert(asset.clone()) {
+                    replies.push(Response::AssetFailed(asset, reason));
+                }
+            }
+        }
+
+        // peers feed the input, wait for them if we are too far ahead
+        if let Some(session) = &mut self.session {
+            if !session.advance(rt, self.tick_time, &mut replies)? {
+                self.replies = replies;
+                return Ok(false);
+            }
+        }
+
+        for reply in replies {
+            send_live(&mut self.recorder, &self.player, rt, reply)?;
+        }
+
+        trace!("Tick");
+        self.next_tick += self.tick_time;
+        self.frame_runtime = None;
+
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.end_tick() {
+                warn!("Failed to write recording, stopping: {}", e);
+                self.recorder = None;
+            }
+        }
+
+        if let Some(player) = &mut self.player {
+            match player.next_tick() {
+                Some(responses) => {
+                    for response in responses {
+                        rt.send(response)?;
+                    }
+                }
+                None => {
+                    info!("Replay finished, resuming live input");
+                    self.player = None;
+                }
+            }
+        }
+
+        let calls = tick_till_present(rt)?;
+
+        // Adjust the time by one tick. This is determenistic
+        let time = Response::Time(self.tick_time.as_secs_f64());
+        send_live(&mut self.recorder, &self.player, rt, time)?;
+
+        self.fixed_tick(out, calls, present).await;
+        Ok(true)
+    }
+
+    /// Show the last fixed tick as it is, for outputs that only see fixed
+    /// ticks. Interpolated frames are otherwise only shown between them
+    pub async fn present_tick(&mut self, out: &mut impl Output) {
+        if self.presentation() == Presentation::Interpolated {
+            self.present_interpolated(out, 1.0).await;
+        }
+    }
+
+    /// Stop running the game and show what went wrong instead
+    pub fn crash(&mut self, out: &mut impl Output, err: runtime::Error) {
+        let message = err.to_string();
+        error!("Game crashed: {}", message);
+
+        out.crashed(Some(&message));
+        self.crashed = Some(message);
+    }
+
+    /// Leave the crashed state, returning whether we were in it
+    fn recover(&mut self, out: &mut impl Output) -> bool {
+        if self.crashed.take().is_none() {
+            return false;
+        }
+
+        info!("Recovered from crash");
+        out.crashed(None);
+        true
+    }
+
+    /// Throw out everything decoded from assets that changed on disk
+    fn forget_assets(&mut self, out: &mut impl Output, paths: &[PathBuf]) {
+        info!("Reloading {} changed assets", paths.len());
+        out.forget(paths);
+
+        // failures are forgotten as well, the files might be fixed now
+        self.reported.clear();
+
+        self.maps.retain(|_, map| {
+            if !map.sources.iter().any(|source| paths.contains(source)) {
+                return true;
+            }
+
+            out.forget_tilemap(&map.path);
+            false
+        });
+    }
+
+    /// A tile map, read the first time it is asked for. `None` if it can't be
+    async fn tilemap(&mut self, asset: &str) -> Option<Arc<Map>> {
+        if let Some(map) = self.maps.get(asset) {
+            return Some(Arc::clone(map));
+        }
+
+        if self.assets.failure(asset).is_some() {
+            return None;
+        }
+
+        match tilemap::load(&self.assets, asset).await {
+            Ok(map) => {
+                let map = Arc::new(map);
+                self.maps.insert(asset.into(), Arc::clone(&map));
+                Some(map)
+            }
+            Err(e) => {
+                self.assets.fail(asset, e);
+                None
+            }
+        }
+    }
+
+    /// Start decoding assets the game is about to use
+    async fn preload(&mut self, out: &mut impl Output, assets: Vec<String>) {
+        for asset in assets {
+            if !self.preloader.wanted(&asset) {
+                continue;
+            }
+
+            if out.in_atlas(&asset) {
+                self.preloader
+                    .skip(&asset, PathBuf::from(&asset), Ok(Decoded::Nothing));
+                continue;
+            }
+
+            let cache = match self.assets.get(&asset).await {
+                Some(cache) => cache,
+                // already reported as failed, but no longer loading either
+                None => {
+                    self.preloader
+                        .skip(&asset, PathBuf::from(&asset), Ok(Decoded::Nothing));
+                    continue;
+                }
+            };
+
+            if out.has_decoded(&cache.path) {
+                self.preloader
+                    .skip(&asset, cache.path.clone(), Ok(Decoded::Nothing));
+            } else {
+                self.preloader.start(&asset, cache);
+            }
+        }
+    }
+
+    async fn run_till_present(
+        &mut self,
+        out: &mut impl Output,
+        rt: &mut RT,
+    ) -> Result<(), runtime::Error> {
+        puffin::profile_function!();
+
+        // the fixed tick catching up makes every other call again, carrying
+        // them out here too would play sounds twice and answer calls early
+        let calls = tick_till_present(rt)?
+            .into_iter()
+            .filter(|call| matches!(call, Call::Draw(_) | Call::Tilemap(_) | Call::Present))
+            .collect();
+        self.dispatch(out, calls).await;
+
+        Ok(())
+    }
+
+    /// Carry out the calls of a fixed tick. Its draws are kept to interpolate
+    /// between, presented right away, or thrown out when `present` is false
+    async fn fixed_tick(&mut self, out: &mut impl Output, tick: Vec<Call>, present: bool) {
+        puffin::profile_function!();
+
+        out.tick(&tick);
+        self.referenced = self.resolve(out, &tick).await;
+
+        let mut calls = vec![];
+        let mut draws = vec![];
+        let mut tilemaps = vec![];
+        for call in tick {
+            match call {
+                Call::Draw(call) => draws.push(call),
+                Call::Tilemap(call) => tilemaps.push(call),
+                Call::Present => (),
+                call => calls.push(call),
+            }
+        }
+
+        match self.presentation() {
+            Presentation::Interpolated => self.history.push(draws, tilemaps),
+            Presentation::Speculative if present => {
+                calls.extend(draws.into_iter().map(Call::Draw));
+                calls.extend(tilemaps.into_iter().map(Call::Tilemap));
+                calls.push(Call::Present);
+            }
+            Presentation::Speculative => (),
+        }
+        self.dispatch(out, calls).await;
+    }
+
+    /// Look up and decode every asset a fixed tick used, returning their
+    /// names. Only some ticks are shown, this way whether an asset failed
+    /// doesn't depend on which
+    async fn resolve(&mut self, out: &mut impl Output, tick: &[Call]) -> Vec<String> {
+        let mut referenced = vec![];
+        let mut draws = vec![];
+        let mut maps = vec![];
+
+        for call in tick {
+            match call {
+                Call::Draw(draw) => {
+                    referenced.push(draw.asset.clone());
+                    if !out.in_atlas(&draw.asset) {
+                        draws.push(draw.clone());
+                    }
+                }
+                Call::Play(play) => referenced.push(play.asset.clone()),
+                Call::Tilemap(TilemapCall { asset, .. }) | Call::LoadTilemap(asset) => {
+                    maps.push(asset.clone())
+                }
+                Call::Preload(assets) => referenced.extend(assets.iter().cloned()),
+                _ => (),
+            }
+        }
+
+        for draw in &draws {
+            self.assets.get(&draw.asset).await;
+        }
+        out.prepare(&self.assets, &draws).await;
+
+        // tilesets are failed under the name of their image
+        for asset in maps {
+            if let Some(map) = self.tilemap(&asset).await {
+                referenced.extend(map.tilesets.iter().map(|tileset| tileset.image.clone()));
+            }
+            referenced.push(asset);
+        }
+
+        referenced
+    }
+
+    /// Present the draws of the last tick, `alpha` of the way from the tick
+    /// before it
+    async fn present_interpolated(&mut self, out: &mut impl Output, alpha: f32) {
+        puffin::profile_function!();
+
+        let mut calls: Vec<Call> = self
+            .history
+            .blend(alpha)
+            .into_iter()
+            .map(Call::Draw)
+            .collect();
+        calls.extend(self.history.tilemaps().iter().cloned().map(Call::Tilemap));
+        calls.push(Call::Present);
+
+        self.dispatch(out, calls).await;
+    }
+
+    /// Carry out the calls of a single frame
+    async fn dispatch(&mut self, out: &mut impl Output, calls: Vec<Call>) {
+        let mut frame = Frame::default();
+        let mut tilemaps = vec![];
+        let mut rest = vec![];
+
+        for call in calls {
+            match call {
+                Call::Draw(call) => frame.draws.push(call),
+                Call::Play(call) => frame.plays.push(call),
+                Call::Tilemap(call) => tilemaps.push(call),
+                Call::Present => frame.present = true,
+                Call::Screenshot => frame.screenshot = true,
+                call => rest.push(call),
+            }
+        }
+
+        for TilemapCall { asset, trans } in tilemaps {
+            if let Some(map) = self.tilemap(&asset).await {
+                frame.tilemaps.push((map, trans));
+            }
+        }
+
+        out.frame(&self.assets, frame).await;
+
+        for call in rest {
+            match call {
+                Call::Preload(assets) => self.preload(out, assets).await,
+                Call::LoadTilemap(asset) => match self.tilemap(&asset).await {
+                    Some(map) => self.reply(Response::Tilemap(asset, map.data.clone())),
+                    // failures are only reported once, remind the game of old ones
+                    None if self.reported.contains(&asset) => {
+                        if let Some(reason) = self.assets.failure(&asset) {
+                            self.reply(Response::AssetFailed(asset, reason));
+                        }
+                    }
+                    None => (),
+                },
+                Call::Print(msg) => out.print(msg),
+                Call::Configure(config) => self.configure(out, config),
+                Call::State(_) => warn!("Game sent its state outside of a reload"),
+                Call::Crash(msg) => error!("{}", msg),
+                Call::Exit => {
+                    info!("Game exited");
+                    self.exited = true;
+                }
+                Call::Draw(..)
+                | Call::Play(..)
+                | Call::Tilemap(..)
+                | Call::Present
+                | Call::Screenshot => unreachable!(),
+            }
+        }
+    }
+
+    /// Answer a call before the next tick. Asking again before then gets
+    /// the same answer only once
+    fn reply(&mut self, response: Response) {
+        let pending = self.replies.iter().any(|reply| {
+            std::mem::discriminant(reply) == std::mem::discriminant(&response)
+                && reply_asset(reply) == reply_asset(&response)
+        });
+
+        if !pending {
+            self.replies.push(response);
+        }
+    }
+
+    /// How frames are shown. Speculative frames run ahead on a copy of the
+    /// game, runtimes that can't be duplicated or outputs that ask for smooth
+    /// frames always interpolate
+    fn presentation(&self) -> Presentation {
+        if RT::can_duplicate() && !self.smooth {
+            self.config.presentation
+        } else {
+            Presentation::Interpolated
+        }
+    }
+
+    fn configure(&mut self, out: &mut impl Output, change: ConfigChange) {
+        let config = self.config.merge(change);
+
+        // speculative frames repeat the same calls, only apply changes
+        if config == self.config {
+            return;
+        }
+
+        debug!("Applying game configuration: {:?}", config);
+        out.configure(&self.config, &config);
+
+        if config.presentation != self.config.presentation {
+            self.history.clear();
+
+            if config.presentation == Presentation::Speculative && !RT::can_duplicate() {
+                warn!("This runtime can't be duplicated, showing interpolated frames instead");
+            }
+        }
+
+        if config.tick_rate > 0.0 {
+            self.tick_time = Duration::from_secs_f64(1.0 / config.tick_rate);
+        } else {
+            warn!("Ignoring invalid tick rate {}", config.tick_rate);
+        }
+
+        self.frame_cap = config
+            .frame_cap
+            .filter(|fps| *fps > 0.0)
+            .map(|fps| Duration::from_secs_f64(1.0 / fps));
+
+        self.config = config;
+    }
+}
+
+/// Send live input to a runtime, recording it, unless a replay is driving it
+fn send_live<RT: Runtime>(
+    recorder: &mut Option<Recorder>,
+    player: &Option<Player>,
+    rt: &mut RT,
+    response: Response,
+) -> Result<(), runtime::Error> {
+    if player.is_some() {
+        return Ok(());
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.record(&response);
+    }
+
+    rt.send(response)
+}
+
+/// Hand assets that finished decoding over, returning them to tell the game
+fn finish_preloads(
+    preloader: &mut Preloader,
+    assets: &Assets,
+    out: &mut impl Output,
+) -> Vec<String> {
+    let mut ready = vec![];
+    for done in preloader.finished() {
+        match done.decoded {
+            Ok(decoded) => out.preloaded(done.path, decoded),
+            Err(e) => assets.fail(&done.asset, e),
+        }
+        ready.push(done.asset);
+    }
+
+    ready
+}
+
+/// The asset a reply is about
+fn reply_asset(reply: &Response) -> Option<&str> {
+    match reply {
+        Response::AssetFailed(asset, _)
+        | Response::Preloaded(asset)
+        | Response::Tilemap(asset, _) => Some(asset),
+        _ => None,
+    }
+}
+
+/// Run the game until it completes a frame, returning every call it made
+pub fn tick_till_present<RT: Runtime>(rt: &mut RT) -> Result<Vec<Call>, runtime::Error> {
+    puffin::profile_function!();
+
+    let mut calls = vec![];
+    loop {
+        let tick = rt.run_tick()?;
+
+        // stop ticking once we complete a frame
+        let presented = tick.iter().any(|call| matches!(call, Call::Present));
+        calls.extend(tick);
+
+        if presented {
+            return Ok(calls);
+        }
+    }
+}
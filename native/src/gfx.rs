@@ -1,6 +1,16 @@
-use std::{collections::HashMap, path::PathBuf, rc::Rc, sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::Instant,
+};
 
 use glam::Mat4;
+use image::{
+    imageops::{self, FilterType},
+    RgbaImage,
+};
 use rend3::{
     datatypes::{
         AffineTransform, AlbedoComponent, Camera, Material, MaterialHandle, Mesh, MeshBuilder,
@@ -10,11 +20,15 @@ use rend3::{
 };
 use rend3_list::{DefaultPipelines, DefaultShaders};
 use tracing::*;
-use vg_types::Transform;
+use vg_types::{Filter, Sampling, Transform, Wrap};
 use wgpu::*;
 use winit::{dpi::PhysicalSize, window::Window};
 
-use crate::assets::Cache;
+use crate::{
+    assets::Cache,
+    atlas::{Manifest, Region},
+    tilemap::Map,
+};
 
 const PREFERRED_FORMAT: TextureFormat = TextureFormat::Bgra8Unorm;
 // const PREFERRED_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
@@ -32,12 +46,96 @@ pub struct Gfx {
     pipelines: DefaultPipelines,
     #[cfg(feature = "debug")]
     pub egui_pass: egui_wgpu_backend::RenderPass,
-    objects: Vec<ObjectHandle>,
-    materials: Vec<MaterialHandle>,
-    textures: HashMap<PathBuf, TextureHandle>,
+    /// Uploaded textures and the size of their image
+    textures: HashMap<TextureKey, (TextureHandle, [u32; 2])>,
+    sprites: HashMap<SpriteKey, Pool>,
     sprite_mesh: MeshHandle,
+    atlas: Option<Atlas>,
+    /// Tile maps by the path they were read from
+    tilemaps: HashMap<PathBuf, Tiles>,
+    /// Where frames are read back from, made on the first capture
+    readback: Option<Readback>,
+    /// Size of the message shown in place of the game, if there is one
+    message: Option<[u32; 2]>,
+}
+
+/// Offscreen texture frames are drawn into to be read back, and the buffer
+/// they are copied to
+struct Readback {
+    texture: Texture,
+    view: Arc<TextureView>,
+    buffer: Buffer,
+    size: [u32; 2],
+    /// Bytes per row of the buffer, padded as copies require
+    row: u32,
 }
 
+/// A tile map as one mesh per layer and tileset, with their objects pooled
+/// like sprites so the same map can be drawn several times
+struct Tiles {
+    pools: Vec<Pool>,
+    materials: Vec<MaterialHandle>,
+    textures: Vec<TextureHandle>,
+}
+
+/// Pages of the texture atlas built by cargo-vg and where each sprite lies on
+/// them
+struct Atlas {
+    regions: HashMap<String, Region>,
+    pages: Vec<(TextureHandle, [u32; 2])>,
+    /// Material per page and filter, shared by every sprite on the page
+    materials: HashMap<(usize, bool), MaterialHandle>,
+}
+
+/// Path of a texture, whether it has mipmaps and whether its edges are
+/// clamped. Clamped textures get a border of their edge pixels, which sprites
+/// leave out of their quad. Mirroring only differs from clamping further than
+/// half a pixel past the edge, which sprites never sample
+type TextureKey = (PathBuf, bool, bool);
+
+/// Texture and whether it is sampled nearest. Sprites sharing both share a
+/// material
+type SpriteKey = (TextureKey, bool);
+
+/// Objects of a single material, kept across frames and moved into place
+/// instead of being created for every draw
+struct Pool {
+    material: MaterialHandle,
+    /// Atlas sprites and clamped textures show part of their texture with a
+    /// mesh of their own
+    mesh: Option<MeshHandle>,
+    /// Atlas sprites and tile maps share the material of their texture
+    owns_material: bool,
+    objects: Vec<ObjectHandle>,
+    /// Objects drawn this frame
+    used: usize,
+    /// Objects drawn last frame, the rest are already hidden
+    shown: usize,
+    /// Frames in a row nothing was drawn from this pool
+    idle: u32,
+}
+
+/// Transform of pooled objects that are not drawn this frame
+const HIDDEN: AffineTransform = AffineTransform {
+    transform: Mat4::ZERO,
+};
+
+/// Texture of the message shown in place of the game
+const MESSAGE: &str = "<message>";
+
+/// Message pixels stay sharp and keep their edges to themselves
+const MESSAGE_SAMPLING: Sampling = Sampling {
+    filter: Some(Filter::Nearest),
+    wrap: Wrap::Clamp,
+    mipmaps: false,
+};
+
+/// Unused objects kept around per material, the rest are freed
+const POOL_SLACK: usize = 64;
+
+/// Frames a pool can go without draws before it is freed, material included
+const POOL_IDLE_FRAMES: u32 = 600;
+
 impl Gfx {
     pub async fn new(window: Arc<Window>) -> Gfx {
         //wgpu_subscriber::initialize_default_subscriber(Some(std::path::Path::new("wgpu_trace")));
@@ -169,21 +267,7 @@ impl Gfx {
             });
         }
 
-        let sprite_mesh = MeshBuilder::new(vec![
-            (-0.5, -0.5, 0.0).into(),
-            (0.5, -0.5, 0.0).into(),
-            (0.5, 0.5, 0.0).into(),
-            (-0.5, 0.5, 0.0).into(),
-        ])
-        .with_vertex_uvs(vec![
-            (0.0, 1.0).into(),
-            (1.0, 1.0).into(),
-            (1.0, 0.0).into(),
-            (0.0, 0.0).into(),
-        ])
-        .with_indices(vec![2, 1, 0, 0, 3, 2, 2, 3, 0, 0, 1, 2])
-        .build();
-        let sprite_mesh = renderer.add_mesh(sprite_mesh);
+        let sprite_mesh = renderer.add_mesh(quad([0.0, 0.0], [1.0, 1.0]));
 
         #[cfg(feature = "debug")]
         let egui_pass = {
@@ -202,10 +286,13 @@ impl Gfx {
             swapchain,
             renderer,
             pipelines,
-            objects: vec![],
-            materials: vec![],
             textures: HashMap::new(),
+            sprites: HashMap::new(),
             sprite_mesh,
+            atlas: None,
+            tilemaps: HashMap::new(),
+            readback: None,
+            message: None,
             #[cfg(feature = "debug")]
             egui_pass,
         }
@@ -217,6 +304,19 @@ impl Gfx {
             .create_swap_chain(&self.surface, &self.swapchain_desc);
     }
 
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.swapchain_desc.present_mode = if vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Mailbox
+        };
+        debug!(
+            "Using present mode of {:?}",
+            self.swapchain_desc.present_mode
+        );
+        self.recreate_swapchain();
+    }
+
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         trace!("Resizing to {}x{}", size.width, size.height);
         self.swapchain_desc.width = size.width;
@@ -229,45 +329,342 @@ impl Gfx {
         })
     }
 
-    pub async fn draw_sprite(&mut self, asset: Arc<Cache>, transform: Transform) {
+    /// Drop textures of changed assets, they are uploaded again the next time
+    /// they are drawn
+    pub fn forget(&mut self, paths: &[PathBuf]) {
+        let renderer = &self.renderer;
+
+        // atlas sprites go by asset name, the rest by full path
+        self.sprites.retain(|((path, ..), _), pool| {
+            if !paths.iter().any(|changed| changed.ends_with(path)) {
+                return true;
+            }
+
+            pool.free(renderer);
+            false
+        });
+
+        // changed sprites are drawn from their own texture until the atlas
+        // is built again
+        if let Some(atlas) = &mut self.atlas {
+            atlas
+                .regions
+                .retain(|name, _| !paths.iter().any(|changed| changed.ends_with(name)));
+        }
+
+        self.textures.retain(|(path, ..), (tex, _)| {
+            if !paths.contains(path) {
+                return true;
+            }
+
+            debug!("Reloading texture {}", path.display());
+            renderer.remove_texture_2d(*tex);
+            false
+        });
+    }
+
+    /// Draw a sprite. A texture that fails to decode is drawn as a placeholder,
+    /// and the error is only returned the first time
+    pub async fn draw_sprite(
+        &mut self,
+        asset: Arc<Cache>,
+        transform: Transform,
+        sampling: Sampling,
+    ) -> Result<(), String> {
         puffin::profile_function!();
 
-        if !self.textures.contains_key(&asset.path) {
-            let bytes = asset.load_all().await;
+        let result = self.load_sprite(&asset, sampling).await;
+        self.place(
+            texture_key(asset.path.clone(), sampling),
+            transform,
+            sampling,
+        );
+        result
+    }
+
+    /// Decode and upload the texture of a sprite ahead of drawing it, in
+    /// place of a placeholder if it fails to read or decode
+    pub async fn load_sprite(&mut self, asset: &Cache, sampling: Sampling) -> Result<(), String> {
+        let key = texture_key(asset.path.clone(), sampling);
+        if self.textures.contains_key(&key) {
+            return Ok(());
+        }
+
+        let decoded = match asset.load_all().await {
+            Ok(bytes) => image::load_from_memory(&bytes).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match decoded {
+            Ok(img) => {
+                self.upload(key, img.to_rgba8());
+                Ok(())
+            }
+            Err(e) => {
+                self.upload(key, placeholder());
+                Err(e)
+            }
+        }
+    }
+
+    /// Draw sprites packed into an atlas from its pages from now on, in place
+    /// of any previous atlas
+    pub fn set_atlas(&mut self, manifest: Manifest, pages: Vec<RgbaImage>) {
+        let renderer = &self.renderer;
+
+        if let Some(old) = self.atlas.take() {
+            self.sprites.retain(|_, pool| {
+                if pool.owns_material {
+                    return true;
+                }
+
+                pool.free(renderer);
+                false
+            });
+            for material in old.materials.values() {
+                renderer.remove_material(*material);
+            }
+            for (tex, _) in old.pages {
+                renderer.remove_texture_2d(tex);
+            }
+        }
+
+        let pages = pages
+            .into_iter()
+            .map(|img| add_texture(renderer, img, false))
+            .collect();
+
+        self.atlas = Some(Atlas {
+            regions: manifest.regions,
+            pages,
+            materials: HashMap::new(),
+        });
+    }
 
-            let img = image::load_from_memory(&bytes).unwrap();
-            let img = img.to_rgba8();
+    /// Draw a sprite from the atlas, returning false when it is not in there.
+    /// Mipmapped draws need a texture of their own
+    pub fn draw_atlas(&mut self, asset: &str, transform: Transform, sampling: Sampling) -> bool {
+        let atlas = match &mut self.atlas {
+            Some(atlas) if !sampling.mipmaps => atlas,
+            _ => return false,
+        };
+        let region = match atlas.regions.get(asset) {
+            Some(region) => *region,
+            None => return false,
+        };
 
-            let tex = self.renderer.add_texture_2d(rend3::datatypes::Texture {
-                width: img.width(),
-                height: img.height(),
-                data: img.into_raw(),
-                format: RendererTextureFormat::Rgba8Srgb,
-                label: None,
-                mip_levels: 1,
+        let size = [region.width, region.height];
+        let filter = sampling.filter.unwrap_or_else(|| auto_filter(size));
+        let nearest = filter == Filter::Nearest;
+
+        // the padding around atlas sprites clamps their edges, filtering
+        // across a repeating edge needs a texture of its own
+        if !nearest && sampling.wrap == Wrap::Repeat {
+            return false;
+        }
+
+        let renderer = &self.renderer;
+        let (tex, page) = atlas.pages[region.page];
+        let material = *atlas
+            .materials
+            .entry((region.page, nearest))
+            .or_insert_with(|| {
+                debug!("Creating atlas page material");
+                renderer.add_material(sprite_material(tex, nearest))
             });
 
-            self.textures.insert(asset.path.clone(), tex);
-            debug!("Texture uploaded");
+        let pool = self
+            .sprites
+            .entry(((PathBuf::from(asset), false, true), nearest))
+            .or_insert_with(|| {
+                let min = [
+                    region.x as f32 / page[0] as f32,
+                    region.y as f32 / page[1] as f32,
+                ];
+                let max = [
+                    (region.x + region.width) as f32 / page[0] as f32,
+                    (region.y + region.height) as f32 / page[1] as f32,
+                ];
+                Pool::new(material, Some(renderer.add_mesh(quad(min, max))), false)
+            });
+
+        pool.place(renderer, self.sprite_mesh, transform);
+        true
+    }
+
+    /// Whether an asset is packed into the atlas
+    pub fn in_atlas(&self, asset: &str) -> bool {
+        self.atlas
+            .as_ref()
+            .map_or(false, |atlas| atlas.regions.contains_key(asset))
+    }
+
+    /// Whether a texture is already uploaded for draws without mipmaps
+    pub fn has_texture(&self, path: &Path) -> bool {
+        self.textures
+            .contains_key(&(path.to_path_buf(), false, false))
+    }
+
+    /// Upload a texture decoded ahead of time. Draws with mipmaps still
+    /// upload their own copy on first use
+    pub fn preloaded(&mut self, path: PathBuf, img: RgbaImage) {
+        // drawn while it was decoding, keep what is already uploaded
+        let key = (path, false, false);
+        if !self.textures.contains_key(&key) {
+            self.upload(key, img);
+        }
+    }
+
+    /// Draw a magenta checkerboard in place of an asset that could not be found
+    pub fn draw_placeholder(&mut self, asset: &str, transform: Transform, sampling: Sampling) {
+        let key = texture_key(PathBuf::from(asset), sampling);
+        if !self.textures.contains_key(&key) {
+            self.upload(key.clone(), placeholder());
+        }
+
+        self.place(key, transform, sampling);
+    }
+
+    /// Show an image of text in the middle of every frame, or stop showing
+    /// it with `None`. Nothing else needs to be drawn for it to show
+    pub fn set_message(&mut self, image: Option<RgbaImage>) {
+        match image {
+            Some(image) => {
+                self.message = Some([image.width(), image.height()]);
+                self.upload(texture_key(MESSAGE.into(), MESSAGE_SAMPLING), image);
+            }
+            None => {
+                self.message = None;
+                self.forget(&[PathBuf::from(MESSAGE)]);
+            }
+        }
+    }
+
+    /// Place the message as large as it fits, with its pixels at most a
+    /// hundredth of the screen height
+    fn draw_message(&mut self, [width, height]: [u32; 2]) {
+        // the camera sees 10 units from the bottom to the top of the screen
+        let aspect = self.swapchain_desc.width as f32 / self.swapchain_desc.height.max(1) as f32;
+        let pixel = (9.0 * aspect / width as f32)
+            .min(9.0 / height as f32)
+            .min(0.1);
+
+        let transform = Transform {
+            position: [0.0; 3],
+            scale: [width as f32 * pixel, height as f32 * pixel, 1.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        };
+        let key = texture_key(MESSAGE.into(), MESSAGE_SAMPLING);
+        self.place(key, transform, MESSAGE_SAMPLING);
+    }
+
+    fn upload(&mut self, key: TextureKey, img: RgbaImage) {
+        let size = [img.width(), img.height()];
+        let img = if key.2 { border(&img) } else { img };
+        let (tex, _) = add_texture(&self.renderer, img, key.1);
+
+        // sprites of a replaced texture get materials for the new one
+        if let Some((old, _)) = self.textures.insert(key.clone(), (tex, size)) {
+            let renderer = &self.renderer;
+            self.sprites.retain(|(texture, _), pool| {
+                if *texture != key {
+                    return true;
+                }
+
+                pool.free(renderer);
+                false
+            });
+            renderer.remove_texture_2d(old);
         }
+    }
+
+    /// Draw the tile layers of a map, turning them into meshes the first time
+    pub fn draw_tilemap(&mut self, map: &Map, transform: Transform) {
+        puffin::profile_function!();
+
+        let renderer = &self.renderer;
+        let tiles = self.tilemaps.entry(map.path.clone()).or_insert_with(|| {
+            let mut tiles = Tiles {
+                pools: vec![],
+                materials: vec![],
+                textures: vec![],
+            };
+
+            // tilesets that failed to load leave their tiles out. Layers that
+            // are not fully opaque get a faded copy of the image
+            let mut materials: HashMap<(usize, u32), MaterialHandle> = HashMap::new();
+            for batch in map.batches() {
+                let tileset = &map.tilesets[batch.tileset];
+                let image = match &map.images[batch.tileset] {
+                    Some(image) => image,
+                    None => continue,
+                };
 
-        let tex = *self.textures.get(&asset.path).unwrap();
+                let material = *materials
+                    .entry((batch.tileset, batch.opacity.to_bits()))
+                    .or_insert_with(|| {
+                        let (tex, _) = add_texture(renderer, fade(image, batch.opacity), false);
+                        let size = [tileset.tile_width, tileset.tile_height];
+                        let nearest = auto_filter(size) == Filter::Nearest;
+                        let material = renderer.add_material(sprite_material(tex, nearest));
 
-        let material = self.renderer.add_material(Material {
-            albedo: AlbedoComponent::Texture(tex),
-            unlit: true,
-            nearest: asset.len <= 128 * 128 * 4, // TODO: Pick sampling better than this
-            //alpha_cutout: Some(0.5),
-            ..Default::default()
+                        tiles.textures.push(tex);
+                        tiles.materials.push(material);
+                        material
+                    });
+
+                let mesh = MeshBuilder::new(batch.positions.into_iter().map(Into::into).collect())
+                    .with_vertex_uvs(batch.uvs.into_iter().map(Into::into).collect())
+                    .with_indices(batch.indices)
+                    .build();
+                tiles
+                    .pools
+                    .push(Pool::new(material, Some(renderer.add_mesh(mesh)), false));
+            }
+
+            debug!("Built {} tile map meshes", tiles.pools.len());
+            tiles
         });
-        let obj = self.renderer.add_object(Object {
-            mesh: self.sprite_mesh,
-            material,
-            transform: trans2mat(transform),
+
+        for pool in &mut tiles.pools {
+            pool.place(renderer, self.sprite_mesh, transform);
+        }
+    }
+
+    /// Drop the meshes of a map, it is built again the next time it is drawn
+    pub fn forget_tilemap(&mut self, path: &Path) {
+        if let Some(mut tiles) = self.tilemaps.remove(path) {
+            debug!("Reloading tile map {}", path.display());
+            tiles.free(&self.renderer);
+        }
+    }
+
+    /// Move a pooled object of an uploaded texture into place
+    fn place(&mut self, key: TextureKey, transform: Transform, sampling: Sampling) {
+        let (tex, size) = *self.textures.get(&key).unwrap();
+        let filter = sampling.filter.unwrap_or_else(|| auto_filter(size));
+        let nearest = filter == Filter::Nearest;
+        let clamped = key.2;
+
+        let renderer = &self.renderer;
+        let pool = self.sprites.entry((key, nearest)).or_insert_with(|| {
+            debug!("Creating sprite material");
+            let material = renderer.add_material(sprite_material(tex, nearest));
+
+            // leave out the border of clamped textures
+            let mesh = Some(size).filter(|_| clamped).map(|[width, height]| {
+                let (width, height) = (width as f32, height as f32);
+                let min = [1.0 / (width + 2.0), 1.0 / (height + 2.0)];
+                let max = [
+                    (width + 1.0) / (width + 2.0),
+                    (height + 1.0) / (height + 2.0),
+                ];
+                renderer.add_mesh(quad(min, max))
+            });
+
+            Pool::new(material, mesh, true)
         });
 
-        self.materials.push(material);
-        self.objects.push(obj);
+        pool.place(renderer, self.sprite_mesh, transform);
     }
 
     // pub fn draw_sprite(&mut self, image: &Image, transform: Transform) {
@@ -326,6 +723,12 @@ impl Gfx {
             frame
         };
 
+        if let Some(size) = self.message {
+            self.draw_message(size);
+        }
+
+        self.hide_unused();
+
         {
             puffin::profile_scope!("rend3_render");
             let render_list = {
@@ -352,16 +755,14 @@ impl Gfx {
             }
         }
 
-        // Remove objects added this frame
-        {
-            puffin::profile_scope!("rend3_clear_scene");
-            for mat in self.materials.drain(..) {
-                self.renderer.remove_material(mat);
-            }
-
-            for obj in self.objects.drain(..) {
-                self.renderer.remove_object(obj);
-            }
+        // Every pool starts the next frame empty
+        let tile_pools = self
+            .tilemaps
+            .values_mut()
+            .flat_map(|tiles| &mut tiles.pools);
+        for pool in self.sprites.values_mut().chain(tile_pools) {
+            pool.shown = pool.used;
+            pool.used = 0;
         }
 
         #[cfg(feature = "debug")]
@@ -443,9 +844,340 @@ impl Gfx {
             puffin::GlobalProfiler::lock().new_frame();
         }
     }
+
+    /// Read back the frame that was just presented. Objects stay in place
+    /// until the next frame, so they are drawn once more into a texture that
+    /// can be copied from. The debug UI is left out
+    pub async fn read_frame(&mut self) -> Result<RgbaImage, String> {
+        puffin::profile_function!();
+
+        let size = [
+            self.swapchain_desc.width.max(1),
+            self.swapchain_desc.height.max(1),
+        ];
+        let extent = Extent3d {
+            width: size[0],
+            height: size[1],
+            depth: 1,
+        };
+
+        if self.readback.as_ref().map_or(true, |rb| rb.size != size) {
+            debug!("Creating {}x{} readback texture", size[0], size[1]);
+
+            let texture = self.device.create_texture(&TextureDescriptor {
+                label: Some("vg-readback"),
+                size: extent,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: self.swapchain_desc.format,
+                usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::COPY_SRC,
+            });
+            let view = Arc::new(texture.create_view(&TextureViewDescriptor::default()));
+
+            let align = COPY_BYTES_PER_ROW_ALIGNMENT;
+            let row = (size[0] * 4 + align - 1) / align * align;
+            let buffer = self.device.create_buffer(&BufferDescriptor {
+                label: Some("vg-readback"),
+                size: row as u64 * size[1] as u64,
+                usage: BufferUsage::COPY_DST | BufferUsage::MAP_READ,
+                mapped_at_creation: false,
+            });
+
+            self.readback = Some(Readback {
+                texture,
+                view,
+                buffer,
+                size,
+                row,
+            });
+        }
+        let readback = self.readback.as_ref().unwrap();
+
+        let render_list =
+            rend3_list::default_render_list(self.renderer.mode(), size, &self.pipelines);
+        self.renderer
+            .render(
+                render_list,
+                RendererOutput::ExternalView(Arc::clone(&readback.view)),
+            )
+            .await;
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("readback-encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            TextureCopyView {
+                texture: &readback.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+            },
+            BufferCopyView {
+                buffer: &readback.buffer,
+                layout: TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: readback.row,
+                    rows_per_image: size[1],
+                },
+            },
+            extent,
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.buffer.slice(..);
+        let mapped = slice.map_async(MapMode::Read);
+        self.device.poll(Maintain::Wait);
+        mapped
+            .await
+            .map_err(|_| "Failed to map the readback buffer".to_string())?;
+
+        let mut image = RgbaImage::new(size[0], size[1]);
+        {
+            let data = slice.get_mapped_range();
+            let rows = data.chunks(readback.row as usize);
+            for (dst, src) in image.chunks_mut(size[0] as usize * 4).zip(rows) {
+                dst.copy_from_slice(&src[..dst.len()]);
+            }
+        }
+        readback.buffer.unmap();
+
+        // the swapchain is usually blue first
+        if let TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb =
+            self.swapchain_desc.format
+        {
+            for pixel in image.pixels_mut() {
+                pixel.0.swap(0, 2);
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Hide pooled objects that were drawn last frame but not this one, and
+    /// free the ones that have not been needed in a while
+    fn hide_unused(&mut self) {
+        puffin::profile_function!();
+
+        let renderer = &self.renderer;
+        self.sprites.retain(|_, pool| {
+            pool.hide_unused(renderer);
+
+            // texture is not drawn anymore, let its material go too
+            if pool.idle > POOL_IDLE_FRAMES {
+                pool.free(renderer);
+                return false;
+            }
+
+            true
+        });
+
+        // maps nobody draws anymore are built again if they come back
+        self.tilemaps.retain(|path, tiles| {
+            for pool in &mut tiles.pools {
+                pool.hide_unused(renderer);
+            }
+
+            if tiles.pools.iter().any(|pool| pool.idle <= POOL_IDLE_FRAMES) {
+                return true;
+            }
+
+            debug!("Freeing tile map {}", path.display());
+            tiles.free(renderer);
+            false
+        });
+    }
+}
+
+impl Tiles {
+    fn free(&mut self, renderer: &Renderer) {
+        for pool in &mut self.pools {
+            pool.free(renderer);
+        }
+        for material in self.materials.drain(..) {
+            renderer.remove_material(material);
+        }
+        for tex in self.textures.drain(..) {
+            renderer.remove_texture_2d(tex);
+        }
+    }
+}
+
+impl Pool {
+    fn new(material: MaterialHandle, mesh: Option<MeshHandle>, owns_material: bool) -> Pool {
+        Pool {
+            material,
+            mesh,
+            owns_material,
+            objects: vec![],
+            used: 0,
+            shown: 0,
+            idle: 0,
+        }
+    }
+
+    /// Move the next unused object into place, or make a new one
+    fn place(&mut self, renderer: &Renderer, sprite_mesh: MeshHandle, transform: Transform) {
+        let transform = trans2mat(transform);
+        match self.objects.get(self.used) {
+            Some(obj) => renderer.set_object_transform(*obj, transform),
+            None => self.objects.push(renderer.add_object(Object {
+                mesh: self.mesh.unwrap_or(sprite_mesh),
+                material: self.material,
+                transform,
+            })),
+        }
+        self.used += 1;
+    }
+
+    fn hide_unused(&mut self, renderer: &Renderer) {
+        for obj in self.objects.iter().take(self.shown).skip(self.used) {
+            renderer.set_object_transform(*obj, HIDDEN);
+        }
+
+        while self.objects.len() > self.used + POOL_SLACK {
+            renderer.remove_object(self.objects.pop().unwrap());
+        }
+
+        self.idle = if self.used == 0 { self.idle + 1 } else { 0 };
+    }
+
+    /// Remove every object, and whatever else belongs to this pool alone
+    fn free(&mut self, renderer: &Renderer) {
+        for obj in self.objects.drain(..) {
+            renderer.remove_object(obj);
+        }
+
+        if let Some(mesh) = self.mesh {
+            renderer.remove_mesh(mesh);
+        }
+        if self.owns_material {
+            renderer.remove_material(self.material);
+        }
+    }
+}
+
+fn add_texture(renderer: &Renderer, img: RgbaImage, mipmaps: bool) -> (TextureHandle, [u32; 2]) {
+    let size = [img.width(), img.height()];
+    let (data, mip_levels) = mip_chain(img, mipmaps);
+
+    let tex = renderer.add_texture_2d(rend3::datatypes::Texture {
+        width: size[0],
+        height: size[1],
+        data,
+        format: RendererTextureFormat::Rgba8Srgb,
+        label: None,
+        mip_levels,
+    });
+    debug!("Texture uploaded with {} mip levels", mip_levels);
+
+    (tex, size)
+}
+
+fn sprite_material(tex: TextureHandle, nearest: bool) -> Material {
+    Material {
+        albedo: AlbedoComponent::Texture(tex),
+        unlit: true,
+        nearest,
+        //alpha_cutout: Some(0.5),
+        ..Default::default()
+    }
+}
+
+/// Unit quad showing the part of a texture between two corners in uv space
+fn quad(min: [f32; 2], max: [f32; 2]) -> Mesh {
+    MeshBuilder::new(vec![
+        (-0.5, -0.5, 0.0).into(),
+        (0.5, -0.5, 0.0).into(),
+        (0.5, 0.5, 0.0).into(),
+        (-0.5, 0.5, 0.0).into(),
+    ])
+    .with_vertex_uvs(vec![
+        (min[0], max[1]).into(),
+        (max[0], max[1]).into(),
+        (max[0], min[1]).into(),
+        (min[0], min[1]).into(),
+    ])
+    .with_indices(vec![2, 1, 0, 0, 3, 2, 2, 3, 0, 0, 1, 2])
+    .build()
+}
+
+/// Copy of an image with its alpha multiplied by `opacity`
+fn fade(image: &RgbaImage, opacity: f32) -> RgbaImage {
+    let mut image = image.clone();
+    if opacity < 1.0 {
+        for pixel in image.pixels_mut() {
+            pixel.0[3] = (pixel.0[3] as f32 * opacity).round() as u8;
+        }
+    }
+
+    image
+}
+
+/// Magenta and black checkerboard that stands out from any art
+pub fn placeholder() -> RgbaImage {
+    RgbaImage::from_fn(8, 8, |x, y| {
+        if (x + y) % 2 == 0 {
+            image::Rgba([255, 0, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+/// Texture of a sprite drawn with `sampling`
+fn texture_key(path: PathBuf, sampling: Sampling) -> TextureKey {
+    (path, sampling.mipmaps, sampling.wrap != Wrap::Repeat)
+}
+
+/// Image with its edge pixels repeated once around it
+fn border(img: &RgbaImage) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return img.clone();
+    }
+
+    RgbaImage::from_fn(width + 2, height + 2, |x, y| {
+        let x = x.saturating_sub(1).min(width - 1);
+        let y = y.saturating_sub(1).min(height - 1);
+        *img.get_pixel(x, y)
+    })
+}
+
+/// Small images are most likely pixel art
+pub fn auto_filter(size: [u32; 2]) -> Filter {
+    if size[0] as u64 * size[1] as u64 <= 128 * 128 {
+        Filter::Nearest
+    } else {
+        Filter::Linear
+    }
+}
+
+/// Pixels of every mip level one after another, along with the level count
+fn mip_chain(img: RgbaImage, mipmaps: bool) -> (Vec<u8>, u32) {
+    puffin::profile_function!();
+
+    let mut levels = 1;
+    let mut data = img.as_raw().clone();
+    if !mipmaps {
+        return (data, levels);
+    }
+
+    let mut level = img;
+    while level.width() > 1 || level.height() > 1 {
+        let width = (level.width() / 2).max(1);
+        let height = (level.height() / 2).max(1);
+        level = imageops::resize(&level, width, height, FilterType::Triangle);
+
+        data.extend_from_slice(level.as_raw());
+        levels += 1;
+    }
+
+    (data, levels)
 }
 
-fn trans2mat(trans: Transform) -> AffineTransform {
+pub fn trans2mat(trans: Transform) -> AffineTransform {
     AffineTransform {
         transform: Mat4::from_scale_rotation_translation(
             trans.scale.into(),
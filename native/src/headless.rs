@@ -0,0 +1,180 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use futures::future::LocalBoxFuture;
+use image::RgbaImage;
+use tokio::runtime::Runtime as Tokio;
+use tracing::{trace, warn};
+use vg_types::{Call, DrawCall, PlayCall, Response};
+
+use crate::{
+    assets::Assets,
+    game::{Frame, Game, Output},
+    runtime::{Error, Runtime},
+    software::Software,
+    Options,
+};
+
+/// Runs a game without a window, graphics or audio device. Every call the
+/// game makes is recorded per frame, and input can be scripted ahead of time.
+/// Frames can be drawn with the software renderer to check what they look like
+pub struct Headless<RT> {
+    game: Game<RT>,
+    out: Recording,
+    tokio: Tokio,
+    tick: usize,
+    script: BTreeMap<usize, Vec<Response>>,
+}
+
+/// Keeps the calls of every tick instead of playing them, and draws them
+/// only when asked to
+#[derive(Default)]
+struct Recording {
+    frames: Vec<Vec<Call>>,
+    renderer: Option<Software>,
+    /// Last frame drawn by the renderer
+    image: Option<RgbaImage>,
+}
+
+impl Output for Recording {
+    fn frame<'a>(&'a mut self, assets: &'a Assets, frame: Frame) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async move {
+            if let (true, Some(renderer)) = (frame.present, &mut self.renderer) {
+                let image = renderer.render(assets, &frame.draws, &frame.tilemaps);
+                self.image = Some(image.await);
+            }
+        })
+    }
+
+    fn tick(&mut self, calls: &[Call]) {
+        self.frames.push(calls.to_vec());
+    }
+
+    fn forget(&mut self, _paths: &[PathBuf]) {
+        if let Some(renderer) = &mut self.renderer {
+            renderer.forget();
+        }
+    }
+}
+
+impl<RT: Runtime> Headless<RT> {
+    pub fn load(code: &[u8]) -> Result<Headless<RT>, Error> {
+        Headless::load_with(Options::default(), code)
+    }
+
+    /// Load a game with engine settings, assets are read from the same
+    /// search paths as a windowed run. Online sessions are not supported
+    pub fn load_with(opts: Options, code: &[u8]) -> Result<Headless<RT>, Error> {
+        if opts.net.is_some() {
+            return Err("Online sessions can't run headless".into());
+        }
+
+        let tokio = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("vg-worker")
+            .enable_all()
+            .build()?;
+
+        let mut out = Recording::default();
+        let mut game = Game::new(opts);
+        game.load(code, &mut out)?;
+
+        Ok(Headless {
+            game,
+            out,
+            tokio,
+            tick: 0,
+            script: BTreeMap::new(),
+        })
+    }
+
+    /// Draw every tick with the software renderer from now on, at the given
+    /// size
+    pub fn render(mut self, width: u32, height: u32) -> Headless<RT> {
+        self.out.renderer = Some(Software::new(width, height));
+        self
+    }
+
+    /// Send a response to the game right before the given tick runs
+    pub fn schedule(&mut self, tick: usize, response: Response) {
+        self.script.entry(tick).or_default().push(response);
+    }
+
+    /// Run a single fixed tick, returning the calls made during it
+    pub fn tick(&mut self) -> Result<&[Call], Error> {
+        trace!("Headless tick {}", self.tick);
+
+        let Headless {
+            game, out, tokio, ..
+        } = self;
+        game.poll(out);
+
+        if let Some(responses) = self.script.remove(&self.tick) {
+            for response in responses {
+                game.send(response)?;
+            }
+        }
+
+        tokio.block_on(async {
+            game.step(out, true).await?;
+            if out.renderer.is_some() {
+                game.present_tick(out).await;
+            }
+            Ok::<_, Error>(())
+        })?;
+
+        self.tick += 1;
+        Ok(self.out.frames.last().unwrap())
+    }
+
+    /// Run up to `ticks` ticks, stopping early if the game exits
+    pub fn run(&mut self, ticks: usize) -> Result<(), Error> {
+        for _ in 0..ticks {
+            if self.exited() {
+                warn!("Game exited after {} ticks", self.tick);
+                break;
+            }
+
+            self.tick()?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of ticks run so far
+    pub fn ticks(&self) -> usize {
+        self.tick
+    }
+
+    pub fn exited(&self) -> bool {
+        self.game.exited()
+    }
+
+    pub fn runtime(&self) -> &RT {
+        self.game.runtime().expect("Headless game is always loaded")
+    }
+
+    /// All recorded calls, one list per frame
+    pub fn frames(&self) -> &[Vec<Call>] {
+        &self.out.frames
+    }
+
+    /// The last tick as drawn by the software renderer, if it is enabled
+    pub fn image(&self) -> Option<&RgbaImage> {
+        self.out.image.as_ref()
+    }
+
+    /// Sprites drawn on a given frame
+    pub fn draws(&self, frame: usize) -> impl Iterator<Item = &DrawCall> {
+        self.out.frames[frame].iter().filter_map(|call| match call {
+            Call::Draw(draw) => Some(draw),
+            _ => None,
+        })
+    }
+
+    /// Sounds started on a given frame
+    pub fn plays(&self, frame: usize) -> impl Iterator<Item = &PlayCall> {
+        self.out.frames[frame].iter().filter_map(|call| match call {
+            Call::Play(play) => Some(play),
+            _ => None,
+        })
+    }
+}
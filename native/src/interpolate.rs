@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use vg_types::{DrawCall, TilemapCall, Transform};
+
+/// Draw lists of the last two ticks, blended to produce frames in between
+#[derive(Default)]
+pub struct History {
+    previous: Vec<DrawCall>,
+    current: Vec<DrawCall>,
+    tilemaps: Vec<TilemapCall>,
+}
+
+impl History {
+    pub fn push(&mut self, draws: Vec<DrawCall>, tilemaps: Vec<TilemapCall>) {
+        self.previous = std::mem::replace(&mut self.current, draws);
+        self.tilemaps = tilemaps;
+    }
+
+    pub fn clear(&mut self) {
+        self.previous.clear();
+        self.current.clear();
+        self.tilemaps.clear();
+    }
+
+    /// Tile maps of the current tick, they are shown where they are now
+    pub fn tilemaps(&self) -> &[TilemapCall] {
+        &self.tilemaps
+    }
+
+    /// Draws of the current tick, moved `alpha` of the way from where they
+    /// were on the previous tick. Draws without an id, or that just appeared,
+    /// are shown where they are now
+    pub fn blend(&self, alpha: f32) -> Vec<DrawCall> {
+        let previous: HashMap<u64, &Transform> = self
+            .previous
+            .iter()
+            .filter_map(|draw| Some((draw.id?, &draw.trans)))
+            .collect();
+
+        self.current
+            .iter()
+            .map(|draw| {
+                let mut draw = draw.clone();
+                if let Some(from) = draw.id.and_then(|id| previous.get(&id)) {
+                    draw.trans = lerp(from, &draw.trans, alpha);
+                }
+                draw
+            })
+            .collect()
+    }
+}
+
+fn lerp(a: &Transform, b: &Transform, t: f32) -> Transform {
+    let mix = |a: f32, b: f32| a + (b - a) * t;
+    let mix3 = |a: [f32; 3], b: [f32; 3]| [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])];
+
+    // take the short way around
+    let dot: f32 = a.rotation.iter().zip(&b.rotation).map(|(a, b)| a * b).sum();
+    let from = a.rotation.map(|x| if dot < 0.0 { -x } else { x });
+
+    let mut rotation = [0.0; 4];
+    for (out, (a, b)) in rotation.iter_mut().zip(from.iter().zip(&b.rotation)) {
+        *out = mix(*a, *b);
+    }
+    let len = rotation.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if len > 0.0 {
+        rotation.iter_mut().for_each(|x| *x /= len);
+    }
+
+    Transform {
+        position: mix3(a.position, b.position),
+        scale: mix3(a.scale, b.scale),
+        rotation,
+    }
+}
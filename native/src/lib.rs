@@ -1,44 +1,97 @@
 mod assets;
+mod atlas;
+mod capture;
 #[cfg(feature = "debug")]
 mod debug;
+mod font;
+mod game;
 mod gfx;
+mod headless;
+mod interpolate;
+mod net;
+mod pack;
+mod preload;
+mod replay;
 pub mod runtime;
 mod sfx;
+mod software;
+mod tilemap;
 mod util;
 
 use std::{
+    path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
 
-use assets::Assets;
-use futures::future::join_all;
+pub use assets::Assets;
+use assets::Embedded;
+pub use atlas::build_atlas;
+use capture::Capture;
+use futures::future::{join_all, LocalBoxFuture};
+use game::{Frame, Game, Output};
 use gfx::Gfx;
+pub use headless::Headless;
+pub use net::NetOptions;
+pub use pack::build_pack;
+use preload::Decoded;
 use runtime::Runtime;
 use sfx::Sfx;
-use tokio::runtime::Runtime as Tokio;
-use tracing::{debug, info, trace, warn};
+pub use software::{compare, Software};
+use tracing::{debug, info, warn};
 use tracing_subscriber::prelude::*;
-use vg_types::{Call, DrawCall, PlayCall};
+use vg_types::{Config, DrawCall, PlayCall};
 use winit::{
+    dpi::PhysicalSize,
     event::{Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
+/// Shows a game in a window and plays its sounds
 pub struct Engine {
     window: Arc<Window>,
     gfx: Gfx,
     sfx: Sfx,
     start_time: Instant,
-    assets: Assets,
     #[cfg(feature = "debug")]
     debug: debug::DebugData,
-    presented: bool,
+    capture: Capture,
+    /// Title asked for by the game, kept to restore it after a crash
+    title: String,
+    /// Texture atlas changed on disk, loaded again before the next frame
+    atlas_changed: bool,
+}
+
+/// Settings for a single engine session
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Record all input sent to the game into a replay file
+    pub record: Option<PathBuf>,
+    /// Feed input from a replay file instead of the keyboard
+    pub replay: Option<PathBuf>,
+    /// Play online with rollback networking. Recording and replays are not
+    /// available in online sessions
+    pub net: Option<NetOptions>,
+    /// Save every presented frame into this directory
+    pub capture_frames: Option<PathBuf>,
+    /// Directories and archives assets are searched in, in priority order.
+    /// Only `assets/` when empty
+    pub asset_paths: Vec<PathBuf>,
+    /// Assets compiled into the executable, searched before everything else
+    pub embedded_assets: Embedded,
 }
 
 impl Engine {
-    pub fn run<RT, F>(mut idle_task: F) -> !
+    pub fn run<RT, F>(idle_task: F) -> !
+    where
+        RT: Runtime + 'static,
+        F: FnMut() -> Option<Vec<u8>> + 'static,
+    {
+        Engine::run_with::<RT, F>(Options::default(), idle_task)
+    }
+
+    pub fn run_with<RT, F>(opts: Options, mut idle_task: F) -> !
     where
         RT: Runtime + 'static,
         F: FnMut() -> Option<Vec<u8>> + 'static,
@@ -54,12 +107,13 @@ impl Engine {
         // console_error_panic_hook::set_once();
         // tracing_wasm::set_as_global_default();
 
-        let mut runtime = None;
-        let mut tick_runtime = None;
-
         let events = EventLoop::new();
 
-        let mut builder = WindowBuilder::new().with_title("vg-main");
+        let config = Config::default();
+        let mut builder = WindowBuilder::new()
+            .with_title(&config.title)
+            .with_inner_size(PhysicalSize::new(config.size[0], config.size[1]))
+            .with_resizable(config.resizable);
 
         #[cfg(target_os = "windows")]
         {
@@ -87,16 +141,15 @@ impl Engine {
             debug,
             sfx,
             gfx: tokio.block_on(Gfx::new(window.clone())),
-            assets: Assets::new(),
             window,
             start_time: Instant::now(),
-            presented: false,
+            capture: Capture::new(opts.capture_frames.clone()),
+            title: config.title,
+            atlas_changed: false,
         };
 
-        let time_tick = Duration::from_millis(10);
-        let mut next_tick = Instant::now();
-        let mut last_frame = Instant::now();
-        let mut shown_tick = false;
+        let mut game = Game::<RT>::new(opts);
+        tokio.block_on(engine.load_atlas(game.assets()));
 
         events.run(move |ev, _, flow| {
             *flow = ControlFlow::Poll;
@@ -104,21 +157,40 @@ impl Engine {
             // hosting process has decided it is time for us to die
             if let Some(code) = idle_task() {
                 debug!("Idle task reloaded code");
-                tick_runtime = Some(RT::load(&code).expect("Loading the runtime failed"));
-                runtime = None;
+                if let Err(e) = game.load(&code, &mut engine) {
+                    game.crash(&mut engine, e);
+                }
                 return;
             }
 
-            let tick_runtime = if let Some(ref mut rt) = tick_runtime {
-                rt
-            } else {
-                return;
-            };
-
             #[cfg(feature = "debug")]
             {
                 engine.debug.platform.handle_event(&ev);
-                engine.debug.tick_time = time_tick;
+                engine.debug.tick_time = game.tick_time();
+            }
+
+            if game.exited() {
+                *flow = ControlFlow::Exit;
+                return;
+            }
+
+            // keep the window alive and showing the error until a new build loads
+            if game.crashed() {
+                *flow = ControlFlow::WaitUntil(Instant::now() + Duration::from_millis(100));
+
+                match ev {
+                    Event::WindowEvent {
+                        event: WindowEvent::CloseRequested,
+                        ..
+                    } => *flow = ControlFlow::Exit,
+                    Event::WindowEvent {
+                        event: WindowEvent::Resized(size),
+                        ..
+                    } => engine.gfx.resize(size),
+                    Event::MainEventsCleared => tokio.block_on(engine.present()),
+                    _ => (),
+                }
+                return;
             }
 
             match ev {
@@ -140,6 +212,22 @@ impl Engine {
                     debug!("Toggled debug UI visibility");
                     engine.debug.visible = !engine.debug.visible;
                 }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    virtual_keycode: Some(VirtualKeyCode::F12),
+                                    state: winit::event::ElementState::Pressed,
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    debug!("Screenshot requested");
+                    engine.capture.screenshot = true;
+                }
                 Event::WindowEvent {
                     event:
                         WindowEvent::KeyboardInput {
@@ -150,131 +238,226 @@ impl Engine {
                     ..
                 } => {
                     if let Some(key) = input.virtual_keycode.and_then(util::winit_to_key) {
-                        match input.state {
-                            winit::event::ElementState::Pressed => {
-                                tick_runtime.send(vg_types::Response::Down(key))
-                            }
-                            winit::event::ElementState::Released => {
-                                tick_runtime.send(vg_types::Response::Up(key))
-                            }
-                        }
+                        let down = input.state == winit::event::ElementState::Pressed;
+                        game.key(&mut engine, key, down);
                     }
                 }
                 // all events for an update handled
                 Event::MainEventsCleared => {
-                    // we should run fixed tick
-                    if next_tick < Instant::now() && shown_tick {
-                        trace!("Tick");
-                        next_tick += time_tick;
-                        runtime = None;
-
-                        // engine.run_till_present(tick_runtime);
-                        tokio.block_on(engine.run_till_present(tick_runtime));
-
-                        // Adjust the time by one tick. This is determenistic
-                        tick_runtime.send(vg_types::Response::Time(time_tick.as_secs_f64()));
-
-                        #[cfg(feature = "debug")]
-                        {
-                            shown_tick = !engine.debug.force_smooth;
-                        }
-                    } else {
-                        // still waiting for fixed tick, so draw render ticks
-                        shown_tick = true;
-
-                        let frame_runtime = if let Some(ref mut rt) = runtime {
-                            rt
-                        } else {
-                            runtime = Some(tick_runtime.duplicate().unwrap());
-                            runtime.as_mut().unwrap()
-                        };
-
-                        tokio.block_on(engine.run_till_present(frame_runtime));
-
-                        // Pass a frames worth of time. Non-determenistic, but its okay because we rollback each tick
-                        let elapsed = last_frame.elapsed();
-                        frame_runtime.send(vg_types::Response::Time(elapsed.as_secs_f64()));
-                        last_frame += elapsed;
-                    }
+                    tokio.block_on(game.update(&mut engine, Instant::now()));
                 }
                 _ => (),
             }
         })
     }
 
-    async fn run_till_present<RT: Runtime>(&mut self, rt: &mut RT) {
-        puffin::profile_function!();
-
-        let mut calls = vec![];
-        let mut draws = vec![];
-        let mut plays = vec![];
+    /// Draw sprites from the texture atlas, if the game has one
+    async fn load_atlas(&mut self, assets: &Assets) {
+        match atlas::load(assets).await {
+            Ok(Some((manifest, pages))) => self.gfx.set_atlas(manifest, pages),
+            Ok(None) => debug!("No texture atlas"),
+            Err(e) => warn!("Failed to load texture atlas: {}", e),
+        }
+    }
 
-        let mut presented = false;
-        while !presented {
-            for call in rt.run_tick().unwrap().drain(..) {
-                // stop ticking once we complete a frame
-                if matches!(call, Call::Present) {
-                    presented = true;
-                }
+    /// Present an empty frame
+    async fn present(&mut self) {
+        self.gfx
+            .present(
+                #[cfg(feature = "debug")]
+                &mut self.debug,
+            )
+            .await;
+    }
 
-                // split calls into different categories so we can do concurrency
-                match call {
-                    Call::Draw(call) => draws.push(call),
-                    Call::Play(call) => plays.push(call),
-                    call => calls.push(call),
-                }
-            }
+    async fn draw(&mut self, assets: &Assets, frame: Frame) {
+        // cargo vg watch rebuilt the atlas, pages are written first
+        if self.atlas_changed {
+            self.atlas_changed = false;
+            self.load_atlas(assets).await;
         }
 
-        let assets = &self.assets;
-
         // Turn our asset, trans pairs into loading async tasks
         let mut draw_tasks = vec![];
-        for DrawCall { asset, trans } in draws {
-            draw_tasks.push(async move { (assets.get(&asset).await, trans) });
+        for DrawCall {
+            asset,
+            trans,
+            sampling,
+            ..
+        } in frame.draws
+        {
+            // atlas sprites are uploaded already
+            if self.gfx.draw_atlas(&asset, trans, sampling) {
+                continue;
+            }
+
+            draw_tasks.push(async move { (assets.get(&asset).await, asset, trans, sampling) });
         }
 
         let mut play_tasks = vec![];
-        for PlayCall { asset, looping } in plays {
-            play_tasks.push(async move { (assets.get(&asset).await, looping) });
+        for PlayCall { asset, looping } in frame.plays {
+            play_tasks.push(async move { (assets.get(&asset).await, asset, looping) });
         }
 
         let (draws, plays) = futures::join!(join_all(draw_tasks), join_all(play_tasks));
 
-        for (asset, trans) in draws {
-            self.gfx.draw_sprite(asset, trans).await;
+        for (cache, asset, trans, sampling) in draws {
+            match cache {
+                Some(cache) => {
+                    if let Err(e) = self.gfx.draw_sprite(cache, trans, sampling).await {
+                        assets.fail(&asset, e);
+                    }
+                }
+                None => self.gfx.draw_placeholder(&asset, trans, sampling),
+            }
         }
 
-        for (asset, looping) in plays {
-            self.sfx.play_sound(asset, looping).await;
+        for (map, trans) in &frame.tilemaps {
+            self.gfx.draw_tilemap(map, *trans);
         }
 
-        for call in calls {
-            match call {
-                Call::Present => {
-                    self.presented = true;
-                    let runtime = self.start_time.elapsed();
-
-                    #[cfg(feature = "debug")]
-                    self.debug.platform.update_time(runtime.as_secs_f64());
-
-                    self.gfx
-                        .present(
-                            #[cfg(feature = "debug")]
-                            &mut self.debug,
-                        )
-                        .await;
-                }
-                Call::Print(msg) => {
-                    info!("{}", msg);
-                    #[cfg(feature = "debug")]
-                    self.debug.print(msg);
+        // missing sounds are just not heard
+        for (cache, asset, looping) in plays {
+            if let Some(cache) = cache {
+                self.sfx.play_sound(assets, &asset, cache, looping).await;
+            }
+        }
+
+        if frame.screenshot {
+            self.capture.screenshot = true;
+        }
+
+        if frame.present {
+            #[cfg(feature = "debug")]
+            self.debug
+                .platform
+                .update_time(self.start_time.elapsed().as_secs_f64());
+
+            self.present().await;
+
+            if self.capture.wanted() {
+                match self.gfx.read_frame().await {
+                    Ok(image) => self.capture.present(&image),
+                    Err(e) => warn!("Failed to read back frame: {}", e),
                 }
-                Call::Exit => {
-                    panic!()
+            }
+        }
+    }
+}
+
+impl Output for Engine {
+    fn frame<'a>(&'a mut self, assets: &'a Assets, frame: Frame) -> LocalBoxFuture<'a, ()> {
+        Box::pin(self.draw(assets, frame))
+    }
+
+    fn prepare<'a>(
+        &'a mut self,
+        assets: &'a Assets,
+        draws: &'a [DrawCall],
+    ) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async move {
+            for draw in draws {
+                if let Some(cache) = assets.get(&draw.asset).await {
+                    if let Err(e) = self.gfx.load_sprite(&cache, draw.sampling).await {
+                        assets.fail(&draw.asset, e);
+                    }
                 }
-                Call::Play(..) | Call::Draw(..) => unreachable!(),
             }
+        })
+    }
+
+    fn in_atlas(&self, asset: &str) -> bool {
+        self.gfx.in_atlas(asset)
+    }
+
+    fn has_decoded(&self, path: &Path) -> bool {
+        self.gfx.has_texture(path) || self.sfx.has_sound(path)
+    }
+
+    fn preloaded(&mut self, path: PathBuf, decoded: Decoded) {
+        match decoded {
+            Decoded::Texture(img) => self.gfx.preloaded(path, img),
+            Decoded::Sound(frames) => self.sfx.preloaded(path, frames),
+            Decoded::Nothing => (),
+        }
+    }
+
+    fn forget(&mut self, paths: &[PathBuf]) {
+        self.gfx.forget(paths);
+        self.sfx.forget(paths);
+
+        if paths.iter().any(|path| path.ends_with(atlas::MANIFEST)) {
+            self.atlas_changed = true;
+        }
+    }
+
+    fn forget_tilemap(&mut self, path: &Path) {
+        self.gfx.forget_tilemap(path);
+    }
+
+    fn configure(&mut self, old: &Config, new: &Config) {
+        if new.title != old.title {
+            self.window.set_title(&new.title);
+            self.title = new.title.clone();
+        }
+
+        if new.size != old.size {
+            self.window
+                .set_inner_size(PhysicalSize::new(new.size[0], new.size[1]));
+        }
+
+        if new.fullscreen != old.fullscreen {
+            self.window
+                .set_fullscreen(new.fullscreen.then(|| Fullscreen::Borderless(None)));
+        }
+
+        if new.vsync != old.vsync {
+            self.gfx.set_vsync(new.vsync);
+        }
+
+        self.window.set_resizable(new.resizable);
+    }
+
+    fn print(&mut self, msg: String) {
+        info!("{}", msg);
+        #[cfg(feature = "debug")]
+        self.debug.print(msg);
+    }
+
+    fn crashed(&mut self, message: Option<&str>) {
+        match message {
+            Some(message) => {
+                let summary = message.lines().next().unwrap_or_default();
+                self.window
+                    .set_title(&format!("{} (crashed: {})", self.title, summary));
+
+                let title = "The game crashed, waiting for a new build\n\n";
+                self.gfx.set_message(Some(font::text(&[
+                    (title, [255, 0, 255]),
+                    (message, [255, 255, 255]),
+                ])));
+            }
+            None => {
+                self.window.set_title(&self.title);
+                self.gfx.set_message(None);
+            }
+        }
+
+        #[cfg(feature = "debug")]
+        {
+            self.debug.crash = message.map(String::from);
+            self.debug.visible |= message.is_some();
         }
     }
+
+    #[cfg(feature = "debug")]
+    fn overrun(&mut self, dropped: Duration) {
+        self.debug.overruns += 1;
+        self.debug.dropped += dropped;
+    }
+
+    #[cfg(feature = "debug")]
+    fn smooth(&self) -> bool {
+        self.debug.force_smooth
+    }
 }
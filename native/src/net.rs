@@ -0,0 +1,542 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, trace, warn};
+use vg_types::{DeBin, Key, Response, SerBin};
+
+use crate::runtime::{Error, Runtime};
+
+/// How many ticks we are willing to simulate again after a misprediction
+const MAX_ROLLBACK: u64 = 16;
+
+/// Settings for an online session, every player must use the same list
+#[derive(Debug, Clone)]
+pub struct NetOptions {
+    /// Address of every player, in player order
+    pub players: Vec<SocketAddr>,
+    /// Which of the players we are
+    pub local: usize,
+    /// Ticks local input is delayed by, hides some latency from rollbacks
+    pub input_delay: u64,
+    /// Artificial latency added to outgoing packets, for testing
+    pub sim_latency: Duration,
+    /// Fraction of outgoing packets dropped, for testing
+    pub sim_loss: f32,
+}
+
+/// Keys held by a player during a single tick, one bit per `Key`
+type Keys = u64;
+
+#[derive(SerBin, DeBin)]
+struct Packet {
+    player: u8,
+    /// Tick of the first input
+    start: u64,
+    inputs: Vec<Keys>,
+}
+
+impl Packet {
+    /// Decode a packet from the socket, which anyone can send anything to
+    fn decode(bytes: &[u8]) -> Result<Packet, String> {
+        // player, start tick and the length of the input list
+        const HEADER: usize = 1 + 8 + 8;
+        if bytes.len() < HEADER {
+            return Err(format!("only {} bytes", bytes.len()));
+        }
+
+        let mut len = [0; 8];
+        len.copy_from_slice(&bytes[9..HEADER]);
+        let len = u64::from_le_bytes(len);
+        if len > MAX_ROLLBACK || bytes.len() as u64 != HEADER as u64 + len * 8 {
+            return Err(format!("{} inputs in {} bytes", len, bytes.len()));
+        }
+
+        Packet::deserialize_bin(bytes).map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Rollback networking for the tick runtime. Inputs of every player are
+/// exchanged each tick, remote inputs are predicted until they arrive, and
+/// when a prediction turns out wrong the runtime is rewound to a saved
+/// snapshot and simulated again
+pub struct Session<RT> {
+    opts: NetOptions,
+    socket: UdpSocket,
+    /// Next tick to be simulated
+    tick: u64,
+    local_keys: Keys,
+    confirmed: Vec<BTreeMap<u64, Keys>>,
+    /// Inputs each tick was simulated with, predictions included
+    used: BTreeMap<u64, Vec<Keys>>,
+    /// Answers to calls of the game sent right before each tick
+    replies: BTreeMap<u64, Vec<Response>>,
+    /// Runtime state right before each tick's input was applied
+    snapshots: VecDeque<(u64, RT)>,
+    /// Earliest tick that was simulated with a wrong prediction
+    rollback: Option<u64>,
+    outgoing: VecDeque<(Instant, SocketAddr, Vec<u8>)>,
+    rng: u32,
+}
+
+impl<RT: Runtime> Session<RT> {
+    pub fn new(opts: NetOptions) -> Result<Session<RT>, Error> {
+        let addr = *opts
+            .players
+            .get(opts.local)
+            .ok_or("Local player is not in the player list")?;
+
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        debug!(
+            "Netplay as player {} of {} on {}",
+            opts.local,
+            opts.players.len(),
+            addr
+        );
+
+        // nobody has input before the delay has passed
+        let mut confirmed = vec![BTreeMap::new(); opts.players.len()];
+        for inputs in &mut confirmed {
+            for tick in 0..opts.input_delay {
+                inputs.insert(tick, 0);
+            }
+        }
+
+        Ok(Session {
+            socket,
+            tick: 0,
+            local_keys: 0,
+            confirmed,
+            used: BTreeMap::new(),
+            replies: BTreeMap::new(),
+            snapshots: VecDeque::new(),
+            rollback: None,
+            outgoing: VecDeque::new(),
+            rng: 0x9e3779b9 ^ addr.port() as u32,
+            opts,
+        })
+    }
+
+    /// Tells the freshly loaded game about the session
+    pub fn greeting(&self) -> Response {
+        Response::Players(self.opts.local as u8, self.opts.players.len() as u8)
+    }
+
+    pub fn set_key(&mut self, key: Key, down: bool) {
+        let bit = 1 << key as u64;
+        if down {
+            self.local_keys |= bit;
+        } else {
+            self.local_keys &= !bit;
+        }
+    }
+
+    /// Exchange inputs with peers, correct any mispredicted ticks and feed the
+    /// input for the next tick into `rt` along with `replies`, which are taken.
+    /// Returns false if we are too far ahead of a peer and have to wait for
+    /// them instead
+    pub fn advance(
+        &mut self,
+        rt: &mut RT,
+        tick_time: Duration,
+        replies: &mut Vec<Response>,
+    ) -> Result<bool, Error> {
+        puffin::profile_function!();
+
+        // input already sent to peers must never change
+        let local = self.opts.local;
+        let delayed = self.tick + self.opts.input_delay;
+        self.confirmed[local]
+            .entry(delayed)
+            .or_insert(self.local_keys);
+
+        self.send_inputs()?;
+        self.receive_inputs()?;
+
+        for (player, inputs) in self.confirmed.iter().enumerate() {
+            let frontier = contiguous(inputs);
+            if self.tick >= frontier + MAX_ROLLBACK {
+                trace!(
+                    "Waiting for player {} (confirmed up to {})",
+                    player,
+                    frontier
+                );
+                return Ok(false);
+            }
+        }
+
+        if let Some(from) = self.rollback.take() {
+            self.resimulate(rt, from, tick_time)?;
+        }
+
+        self.snapshots.push_back((self.tick, rt.duplicate()?));
+        while self.snapshots.len() as u64 > MAX_ROLLBACK + 1 {
+            self.snapshots.pop_front();
+        }
+
+        self.replies.insert(self.tick, std::mem::take(replies));
+        self.apply_inputs(rt, self.tick)?;
+        self.tick += 1;
+
+        // history older than any possible rollback is not needed anymore
+        let keep = self.tick.saturating_sub(MAX_ROLLBACK * 2);
+        self.used = self.used.split_off(&keep);
+        self.replies = self.replies.split_off(&keep);
+        for inputs in &mut self.confirmed {
+            *inputs = inputs.split_off(&keep);
+        }
+
+        Ok(true)
+    }
+
+    fn resimulate(&mut self, rt: &mut RT, from: u64, tick_time: Duration) -> Result<(), Error> {
+        puffin::profile_function!();
+
+        let index = match self.snapshots.iter().position(|(tick, _)| *tick == from) {
+            Some(index) => index,
+            None => {
+                warn!(
+                    "Cannot roll back to tick {}, the session has desynced",
+                    from
+                );
+                return Ok(());
+            }
+        };
+
+        debug!("Rolling back {} ticks", self.tick - from);
+        *rt = self.snapshots[index].1.duplicate()?;
+        self.snapshots.truncate(index);
+
+        for tick in from..self.tick {
+            self.snapshots.push_back((tick, rt.duplicate()?));
+            self.apply_inputs(rt, tick)?;
+
+            // the original run already drew and played everything
+            crate::game::tick_till_present(rt)?;
+            rt.send(Response::Time(tick_time.as_secs_f64()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Send the changes in held keys of every player to the runtime, and the
+    /// replies the tick was first simulated with
+    fn apply_inputs(&mut self, rt: &mut RT, tick: u64) -> Result<(), Error> {
+        let players = self.opts.players.len();
+        let inputs: Vec<Keys> = (0..players).map(|p| self.input(p, tick)).collect();
+        let previous = tick
+            .checked_sub(1)
+            .and_then(|tick| self.used.get(&tick))
+            .cloned()
+            .unwrap_or_else(|| vec![0; players]);
+
+        for (player, (now, before)) in inputs.iter().zip(&previous).enumerate() {
+            let changed = now ^ before;
+            for key in Key::ALL.iter().copied() {
+                let bit = 1 << key as u64;
+                if changed & bit == 0 {
+                    continue;
+                }
+
+                if now & bit != 0 {
+                    rt.send(Response::PlayerDown(player as u8, key))?;
+                } else {
+                    rt.send(Response::PlayerUp(player as u8, key))?;
+                }
+            }
+        }
+
+        self.used.insert(tick, inputs);
+
+        for reply in self.replies.get(&tick).into_iter().flatten() {
+            rt.send(reply.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Confirmed input of a player, or a prediction that they kept holding
+    /// whatever they held last
+    fn input(&self, player: usize, tick: u64) -> Keys {
+        self.confirmed[player]
+            .range(..=tick)
+            .next_back()
+            .map(|(_, keys)| *keys)
+            .unwrap_or(0)
+    }
+
+    fn send_inputs(&mut self) -> Result<(), Error> {
+        let local = self.opts.local;
+        let end = self.tick + self.opts.input_delay + 1;
+        let start = end.saturating_sub(MAX_ROLLBACK);
+
+        // resend the whole window every time so lost packets do not matter
+        let packet = Packet {
+            player: local as u8,
+            start,
+            inputs: (start..end).map(|tick| self.input(local, tick)).collect(),
+        }
+        .serialize_bin();
+
+        let due = Instant::now() + self.opts.sim_latency;
+        for (player, addr) in self.opts.players.iter().enumerate() {
+            if player == local {
+                continue;
+            }
+
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 17;
+            self.rng ^= self.rng << 5;
+            if (self.rng as f32 / u32::MAX as f32) < self.opts.sim_loss {
+                continue;
+            }
+
+            self.outgoing.push_back((due, *addr, packet.clone()));
+        }
+
+        let now = Instant::now();
+        while matches!(self.outgoing.front(), Some((due, _, _)) if *due <= now) {
+            let (_, addr, bytes) = self.outgoing.pop_front().unwrap();
+            match self.socket.send_to(&bytes, addr) {
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                // peers that have not started yet refuse packets on some platforms
+                Err(e) => trace!("Failed to send to {}: {}", addr, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive_inputs(&mut self) -> Result<(), Error> {
+        let mut buf = [0; 1024];
+        loop {
+            let len = match self.socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let packet = match Packet::decode(&buf[..len]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    warn!("Dropping malformed packet: {}", e);
+                    continue;
+                }
+            };
+
+            let player = packet.player as usize;
+            if player >= self.confirmed.len() || player == self.opts.local {
+                warn!("Dropping packet from unknown player {}", player);
+                continue;
+            }
+
+            for (tick, keys) in (packet.start..).zip(packet.inputs) {
+                if self.confirmed[player].contains_key(&tick) || tick < self.oldest() {
+                    continue;
+                }
+                self.confirmed[player].insert(tick, keys);
+
+                // we already ran this tick with a guess, and guessed wrong
+                if let Some(used) = self.used.get(&tick) {
+                    if used[player] != keys {
+                        self.rollback = Some(self.rollback.map_or(tick, |t| t.min(tick)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Oldest tick we still keep history for
+    fn oldest(&self) -> u64 {
+        self.tick.saturating_sub(MAX_ROLLBACK * 2)
+    }
+}
+
+/// First tick for which input is not known yet
+fn contiguous(inputs: &BTreeMap<u64, Keys>) -> u64 {
+    let mut ticks = inputs.keys();
+    let mut next = match ticks.next() {
+        Some(first) => *first,
+        None => return 0,
+    };
+
+    for tick in ticks {
+        if *tick != next + 1 {
+            break;
+        }
+        next = *tick;
+    }
+
+    next + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vg_types::Call;
+
+    /// Game whose whole state is a hash of everything it was told
+    struct Stub {
+        state: u64,
+    }
+
+    impl Stub {
+        fn mix(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.state = (self.state ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    impl Runtime for Stub {
+        fn load(_code: &[u8]) -> Result<Stub, Error> {
+            Ok(Stub {
+                state: 0xcbf29ce484222325,
+            })
+        }
+
+        fn run_tick(&mut self) -> Result<Vec<Call>, Error> {
+            self.mix(b"tick");
+            Ok(vec![Call::Present])
+        }
+
+        fn send(&mut self, value: Response) -> Result<(), Error> {
+            self.mix(&value.serialize_bin());
+            Ok(())
+        }
+
+        fn serialize(&self) -> Result<Vec<u8>, Error> {
+            Ok(self.state.to_le_bytes().to_vec())
+        }
+
+        fn deserialize(bytes: &[u8]) -> Result<Stub, Error> {
+            let mut state = [0; 8];
+            state.copy_from_slice(bytes);
+            Ok(Stub {
+                state: u64::from_le_bytes(state),
+            })
+        }
+    }
+
+    fn free_addr() -> SocketAddr {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn sessions_converge() {
+        let players = vec![free_addr(), free_addr()];
+        let tick_time = Duration::from_millis(16);
+        let mut peers: Vec<(Session<Stub>, Stub)> = (0..2)
+            .map(|local| {
+                let opts = NetOptions {
+                    players: players.clone(),
+                    local,
+                    input_delay: 1,
+                    sim_latency: Duration::from_millis(5),
+                    sim_loss: 0.3,
+                };
+                // greetings differ between players, so skip them
+                (Session::new(opts).unwrap(), Stub::load(&[]).unwrap())
+            })
+            .collect();
+
+        // inputs change often enough to be mispredicted, then settle
+        const END: u64 = 120;
+        let input = |player: usize, tick: u64| {
+            let key = Key::ALL[(tick as usize / (3 + player)) % 4];
+            Some((key, tick % (2 + player as u64) == 0)).filter(|_| tick < 60)
+        };
+        // answers arrive on a tick now and then, and must survive rollbacks
+        let reply = |tick: u64| {
+            Some(Response::Preloaded(format!("{}.png", tick))).filter(|_| tick % 7 == 0)
+        };
+        let mut steps = 0;
+        while peers.iter().any(|(session, _)| session.tick < END) {
+            steps += 1;
+            assert!(steps < 100_000, "sessions stopped advancing");
+
+            for (player, (session, rt)) in peers.iter_mut().enumerate() {
+                if session.tick >= END {
+                    continue;
+                }
+
+                if let Some((key, down)) = input(player, session.tick) {
+                    session.set_key(key, down);
+                }
+
+                let mut replies = reply(session.tick).into_iter().collect();
+                if session.advance(rt, tick_time, &mut replies).unwrap() {
+                    crate::game::tick_till_present(rt).unwrap();
+                    rt.send(Response::Time(tick_time.as_secs_f64())).unwrap();
+                }
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // the same game run offline, where nothing is ever predicted
+        let mut offline = Stub::load(&[]).unwrap();
+        let mut held: Vec<Keys> = vec![0; 2];
+        let mut previous: Vec<Keys> = vec![0; 2];
+        for tick in 0..END {
+            for (player, (now, before)) in held.iter().zip(&previous).enumerate() {
+                for key in Key::ALL.iter().copied() {
+                    let bit = 1 << key as u64;
+                    if (now ^ before) & bit != 0 && now & bit != 0 {
+                        offline
+                            .send(Response::PlayerDown(player as u8, key))
+                            .unwrap();
+                    } else if (now ^ before) & bit != 0 {
+                        offline.send(Response::PlayerUp(player as u8, key)).unwrap();
+                    }
+                }
+            }
+            if let Some(reply) = reply(tick) {
+                offline.send(reply).unwrap();
+            }
+            crate::game::tick_till_present(&mut offline).unwrap();
+            offline
+                .send(Response::Time(tick_time.as_secs_f64()))
+                .unwrap();
+
+            // local input is delayed by a tick
+            previous = held.clone();
+            for (player, keys) in held.iter_mut().enumerate() {
+                if let Some((key, down)) = input(player, tick) {
+                    let bit = 1 << key as u64;
+                    *keys = if down { *keys | bit } else { *keys & !bit };
+                }
+            }
+        }
+
+        assert_eq!(peers[0].1.state, offline.state);
+        assert_eq!(peers[1].1.state, offline.state);
+    }
+
+    #[test]
+    fn oversized_packets_are_rejected() {
+        let packet = Packet {
+            player: 1,
+            start: 0,
+            inputs: vec![0; MAX_ROLLBACK as usize],
+        }
+        .serialize_bin();
+        assert!(Packet::decode(&packet).is_ok());
+
+        let mut huge = packet.clone();
+        huge[9..17].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Packet::decode(&huge).is_err());
+
+        assert!(Packet::decode(&packet[..packet.len() - 1]).is_err());
+        assert!(Packet::decode(&packet[..4]).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, info};
+use vg_types::{DeBin, SerBin};
+
+use crate::runtime::Error;
+
+const MAGIC: &[u8; 4] = b"VGPK";
+const VERSION: u32 = 1;
+
+/// Where a single asset lives inside an archive
+#[derive(SerBin, DeBin, Debug, Clone)]
+pub struct Entry {
+    /// Offset from the start of the archive
+    pub offset: u64,
+    /// Bytes stored in the archive
+    pub len: u64,
+    /// Compressed with deflate, uncompressed entries can be streamed
+    pub compressed: bool,
+}
+
+/// A `.vgpak` archive: magic, version, a length prefixed index of every asset
+/// by name, then the asset data itself
+pub struct Pack {
+    pub path: PathBuf,
+    entries: HashMap<String, Entry>,
+}
+
+impl Pack {
+    pub fn open(path: &Path) -> Result<Pack, Error> {
+        let mut file = File::open(path)?;
+
+        let mut header = [0; 16];
+        file.read_exact(&mut header)
+            .map_err(|_| format!("{} is not an asset archive", path.display()))?;
+
+        if &header[..4] != MAGIC {
+            return Err(format!("{} is not an asset archive", path.display()).into());
+        }
+
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != VERSION {
+            return Err(format!("Unsupported asset archive version {}", version).into());
+        }
+
+        let mut len = [0; 8];
+        len.copy_from_slice(&header[8..]);
+        let len = u64::from_le_bytes(len);
+
+        // a corrupt length must not make us allocate more than the file holds
+        if len > file.metadata()?.len().saturating_sub(header.len() as u64) {
+            return Err(format!("Corrupt asset archive index in {}", path.display()).into());
+        }
+
+        let mut index = vec![0; len as usize];
+        file.read_exact(&mut index)?;
+
+        let entries = HashMap::<String, Entry>::deserialize_bin(&index)
+            .map_err(|e| format!("Corrupt asset archive index: {:?}", e))?;
+
+        // entries have to be within the data after the index
+        let data = header.len() as u64 + len..=file.metadata()?.len();
+        for (name, entry) in &entries {
+            let end = entry.offset.checked_add(entry.len);
+            if !data.contains(&entry.offset) || !end.map_or(false, |end| data.contains(&end)) {
+                return Err(
+                    format!("Corrupt asset archive entry {} in {}", name, path.display()).into(),
+                );
+            }
+        }
+
+        debug!("Mounted {} assets from {}", entries.len(), path.display());
+
+        Ok(Pack {
+            path: path.into(),
+            entries,
+        })
+    }
+
+    pub fn entry(&self, asset: &str) -> Option<&Entry> {
+        self.entries.get(asset)
+    }
+}
+
+/// Pack every file under `dir` into an archive at `out`. With `compress`,
+/// entries are deflated when that makes them noticeably smaller, so already
+/// compressed images and sounds stay streamable
+pub fn build_pack(dir: &Path, out: &Path, compress: bool) -> Result<(), Error> {
+    let files = vg_types::asset_files(dir)?;
+
+    let mut data = vec![];
+    for (name, path) in files {
+        let raw = std::fs::read(&path)?;
+
+        let deflated = if compress {
+            Some(miniz_oxide::deflate::compress_to_vec(&raw, 6))
+        } else {
+            None
+        };
+
+        match deflated {
+            Some(deflated) if deflated.len() < raw.len() * 9 / 10 => {
+                data.push((name, deflated, true))
+            }
+            _ => data.push((name, raw, false)),
+        }
+    }
+
+    // offsets do not change the size of the index, so measure it first
+    let mut entries: Vec<(String, Entry)> = data
+        .iter()
+        .map(|(name, bytes, compressed)| {
+            let entry = Entry {
+                offset: 0,
+                len: bytes.len() as u64,
+                compressed: *compressed,
+            };
+            (name.clone(), entry)
+        })
+        .collect();
+
+    let mut offset = 16 + index(&entries).len() as u64;
+    for (_, entry) in &mut entries {
+        entry.offset = offset;
+        offset += entry.len;
+    }
+
+    let index = index(&entries);
+    let mut file = BufWriter::new(File::create(out)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    file.write_all(&(index.len() as u64).to_le_bytes())?;
+    file.write_all(&index)?;
+    for (_, bytes, _) in &data {
+        file.write_all(bytes)?;
+    }
+    file.flush()?;
+
+    info!(
+        "Packed {} assets into {} ({} bytes)",
+        data.len(),
+        out.display(),
+        offset
+    );
+
+    Ok(())
+}
+
+/// The index as a serialized map, written in the order of `entries`. Files
+/// come sorted by name, so the same assets always pack to the same bytes
+fn index(entries: &[(String, Entry)]) -> Vec<u8> {
+    let mut index = vec![];
+    entries.len().ser_bin(&mut index);
+    for (name, entry) in entries {
+        name.ser_bin(&mut index);
+        entry.ser_bin(&mut index);
+    }
+
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::Assets;
+
+    /// A fresh directory in the temp dir, named after the test
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vg-pack-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("assets/sub")).unwrap();
+        dir
+    }
+
+    /// Text that deflates well, and bytes that don't
+    fn files() -> Vec<(&'static str, Vec<u8>)> {
+        let mut noise = 1u32;
+        let noise = (0..40_000)
+            .map(|_| {
+                noise ^= noise << 13;
+                noise ^= noise >> 17;
+                noise ^= noise << 5;
+                noise as u8
+            })
+            .collect();
+
+        vec![
+            ("a.txt", b"hello hello hello hello hello".repeat(100)),
+            ("sub/noise.bin", noise),
+            ("z.txt", b"!".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn packs_and_unpacks() {
+        let dir = scratch("round-trip");
+        for (name, bytes) in files() {
+            std::fs::write(dir.join("assets").join(name), bytes).unwrap();
+        }
+
+        let (first, second) = (dir.join("first.vgpak"), dir.join("second.vgpak"));
+        build_pack(&dir.join("assets"), &first, true).unwrap();
+        build_pack(&dir.join("assets"), &second, true).unwrap();
+        assert_eq!(
+            std::fs::read(&first).unwrap(),
+            std::fs::read(&second).unwrap()
+        );
+
+        let pack = Pack::open(&first).unwrap();
+        assert!(pack.entry("a.txt").unwrap().compressed);
+        assert!(!pack.entry("sub/noise.bin").unwrap().compressed);
+        assert!(pack.entry("missing.txt").is_none());
+
+        let tokio = tokio::runtime::Runtime::new().unwrap();
+        let assets = Assets::new(&[first], &[]);
+        for (name, bytes) in files() {
+            let cache = tokio.block_on(assets.find(name)).unwrap();
+            assert_eq!(tokio.block_on(cache.load_all()).unwrap(), bytes, "{}", name);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_entries_past_the_end() {
+        let dir = scratch("truncated");
+        for (name, bytes) in files() {
+            std::fs::write(dir.join("assets").join(name), bytes).unwrap();
+        }
+
+        let path = dir.join("assets.vgpak");
+        build_pack(&dir.join("assets"), &path, false).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+
+        let err = Pack::open(&path).err().unwrap().to_string();
+        assert!(err.contains("z.txt"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+};
+
+use image::RgbaImage;
+use oddio::Frames;
+use tracing::debug;
+
+use crate::{assets::Cache, sfx};
+
+/// An asset decoded ahead of time, ready to be handed to the GPU or mixer
+pub enum Decoded {
+    Texture(RgbaImage),
+    Sound(Arc<Frames<[f32; 2]>>),
+    /// Nothing to do up front, like sounds that are streamed
+    Nothing,
+}
+
+/// A preloaded asset, with where it came from
+pub struct Finished {
+    pub asset: String,
+    pub path: PathBuf,
+    pub decoded: Result<Decoded, String>,
+}
+
+/// Decodes assets the game asked for ahead of time on worker threads. Only
+/// uploading is left for the main thread, so first draws and plays no longer
+/// hitch
+pub struct Preloader {
+    /// Assets being decoded right now, games repeat requests every frame
+    loading: HashSet<String>,
+    finished_tx: Sender<Finished>,
+    finished: Receiver<Finished>,
+}
+
+impl Preloader {
+    pub fn new() -> Preloader {
+        let (finished_tx, finished) = channel();
+
+        Preloader {
+            loading: HashSet::new(),
+            finished_tx,
+            finished,
+        }
+    }
+
+    /// Whether an asset still has to be started
+    pub fn wanted(&self, asset: &str) -> bool {
+        !self.loading.contains(asset)
+    }
+
+    /// Report an asset as preloaded right away, because it already is or
+    /// never will be
+    pub fn skip(&mut self, asset: &str, path: PathBuf, decoded: Result<Decoded, String>) {
+        let _ = self.finished_tx.send(Finished {
+            asset: asset.into(),
+            path,
+            decoded,
+        });
+    }
+
+    /// Read and decode an asset on a worker thread. Has to be called from
+    /// inside the tokio runtime
+    pub fn start(&mut self, asset: &str, cache: Arc<Cache>) {
+        debug!("Preloading {}", asset);
+        self.loading.insert(asset.into());
+
+        let asset = asset.to_string();
+        let path = cache.path.clone();
+        let tx = self.finished_tx.clone();
+        tokio::spawn(async move {
+            // the work runs as a task of its own, so even a panic is reported
+            // and the asset doesn't stay loading forever
+            let decoded = match tokio::spawn(read_and_decode(cache)).await {
+                Ok(decoded) => decoded,
+                Err(e) => Err(format!("Preloading failed: {}", e)),
+            };
+
+            let _ = tx.send(Finished {
+                asset,
+                path,
+                decoded,
+            });
+        });
+    }
+
+    /// Assets done decoding since the last call. Those still being decoded
+    /// are handed over by a later call, without waiting for them
+    pub fn finished(&mut self) -> Vec<Finished> {
+        let finished: Vec<Finished> = self.finished.try_iter().collect();
+        for done in &finished {
+            self.loading.remove(&done.asset);
+        }
+
+        finished
+    }
+}
+
+async fn read_and_decode(cache: Arc<Cache>) -> Result<Decoded, String> {
+    let bytes = cache.load_all().await.map_err(|e| e.to_string())?;
+    let path = cache.path.clone();
+
+    match tokio::task::spawn_blocking(move || decode(&path, bytes)).await {
+        Ok(decoded) => decoded,
+        Err(e) => Err(format!("Decoding failed: {}", e)),
+    }
+}
+
+/// Sounds are told apart by their extension, everything else is an image
+fn decode(path: &std::path::Path, bytes: Vec<u8>) -> Result<Decoded, String> {
+    puffin::profile_function!();
+
+    if path.extension().map_or(false, |ext| ext == "ogg") {
+        if bytes.len() > sfx::DECODE_LIMIT {
+            return Ok(Decoded::Nothing);
+        }
+
+        return sfx::decode_all(bytes)
+            .map(Decoded::Sound)
+            .map_err(|e| format!("Vorbis decode error: {:?}", e));
+    }
+
+    image::load_from_memory(&bytes)
+        .map(|img| Decoded::Texture(img.to_rgba8()))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::Assets;
+
+    #[test]
+    fn reports_assets_that_fail_to_read() {
+        let dir = std::env::temp_dir().join(format!("vg-preload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // longer than what is read up front, so the rest is read when preloading
+        std::fs::write(dir.join("gone.png"), vec![0; 64 * 1024]).unwrap();
+
+        let tokio = tokio::runtime::Runtime::new().unwrap();
+        let assets = Assets::new(&[dir.clone()], &[]);
+        let cache = tokio.block_on(assets.get("gone.png")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut preloader = Preloader::new();
+        tokio.block_on(async { preloader.start("gone.png", cache) });
+        assert!(!preloader.wanted("gone.png"));
+
+        let mut finished = vec![];
+        while finished.is_empty() {
+            finished = preloader.finished();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert_eq!(finished[0].asset, "gone.png");
+        assert!(finished[0].decoded.is_err());
+        assert!(preloader.wanted("gone.png"));
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use tracing::{debug, warn};
+use vg_types::{DeBin, Response, SerBin};
+
+use crate::runtime::Error;
+
+const MAGIC: &[u8; 4] = b"VGRP";
+const VERSION: u32 = 1;
+
+/// Writes every response sent to the tick runtime into a replay file, one
+/// record per tick, after a header naming the game code it was recorded
+/// with. Records are flushed as they are written, so a crashing session
+/// still leaves a usable replay behind
+pub struct Recorder {
+    file: BufWriter<File>,
+    tick: Vec<Response>,
+}
+
+impl Recorder {
+    /// Start recording a game whose code hashes to `code_hash`
+    pub fn create(path: &Path, code_hash: u64) -> Result<Recorder, Error> {
+        debug!("Recording input to {}", path.display());
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        file.write_all(&code_hash.to_le_bytes())?;
+
+        Ok(Recorder { file, tick: vec![] })
+    }
+
+    pub fn record(&mut self, response: &Response) {
+        self.tick.push(response.clone());
+    }
+
+    /// Write out everything sent before the tick that is about to run
+    pub fn end_tick(&mut self) -> Result<(), Error> {
+        let bytes = self.tick.serialize_bin();
+        self.tick.clear();
+
+        self.file.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.file.write_all(&bytes)?;
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Feeds recorded responses back to the tick runtime
+pub struct Player {
+    ticks: Vec<Vec<Response>>,
+    cursor: usize,
+}
+
+impl Player {
+    /// Load a replay to feed to a game whose code hashes to `code_hash`. Input
+    /// recorded with other code is still played, but will likely not do the
+    /// same thing
+    pub fn open(path: &Path, code_hash: u64) -> Result<Player, Error> {
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        if bytes.len() < 16 || &bytes[..4] != MAGIC {
+            return Err(format!("{} is not a replay file", path.display()).into());
+        }
+
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != VERSION {
+            return Err(format!("Unsupported replay version {}", version).into());
+        }
+
+        let mut recorded = [0; 8];
+        recorded.copy_from_slice(&bytes[8..16]);
+        if u64::from_le_bytes(recorded) != code_hash {
+            warn!(
+                "{} was recorded with a different build of the game, it will likely play out differently",
+                path.display()
+            );
+        }
+
+        let mut ticks = vec![];
+        let mut rest = &bytes[16..];
+        // a truncated final record means the recording session crashed
+        while rest.len() >= 4 {
+            let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            if rest.len() < 4 + len {
+                break;
+            }
+
+            let tick = Vec::<Response>::deserialize_bin(&rest[4..][..len])
+                .map_err(|e| format!("Corrupt replay record: {:?}", e))?;
+            ticks.push(tick);
+            rest = &rest[4 + len..];
+        }
+
+        debug!("Replaying {} ticks from {}", ticks.len(), path.display());
+
+        Ok(Player { ticks, cursor: 0 })
+    }
+
+    /// Responses to send before the next tick, `None` once the replay is over
+    pub fn next_tick(&mut self) -> Option<Vec<Response>> {
+        let tick = self.ticks.get(self.cursor)?.clone();
+        self.cursor += 1;
+        Some(tick)
+    }
+}
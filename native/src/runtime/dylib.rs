@@ -0,0 +1,175 @@
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use libloading::Library;
+use tracing::*;
+
+use super::{Error, Runtime};
+
+use vg_types::*;
+
+thread_local! {
+    /// Calls made by whichever library is currently running on this thread
+    static CALLS: RefCell<Vec<Call>> = RefCell::new(vec![]);
+}
+
+/// Every loaded copy needs its own file, or the loader hands us back the copy
+/// that is already loaded along with its statics
+static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+struct Exports {
+    tick: extern "C" fn(),
+    allocate: extern "C" fn(len: u64) -> u64,
+    save: extern "C" fn(),
+    allocate_state: extern "C" fn(len: u64) -> u64,
+}
+
+/// Runs the game compiled for the host as a native library. Much faster than
+/// interpreting wasm and can be stepped through in a native debugger, but
+/// native state cannot be copied, so frames are always interpolated and
+/// online sessions are not available
+pub struct Dylib {
+    exports: Exports,
+    library: Option<Library>,
+    path: PathBuf,
+}
+
+extern "C" fn host_call(ptr: u64, len: u64) {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
+
+    match Call::deserialize_bin(bytes) {
+        Ok(call) => CALLS.with(|calls| calls.borrow_mut().push(call)),
+        Err(e) => error!("Game made a malformed call: {:?}", e),
+    }
+}
+
+fn symbol<T: Copy>(library: &Library, name: &str) -> Result<T, Error> {
+    match unsafe { library.get::<T>(name.as_bytes()) } {
+        Ok(symbol) => Ok(*symbol),
+        Err(e) => Err(format!("Couldn't get {}: {}", name, e).into()),
+    }
+}
+
+/// Run some game code, returning the calls it made or the message it
+/// panicked with
+fn collect(f: impl FnOnce()) -> Result<Vec<Call>, Error> {
+    CALLS.with(|calls| calls.borrow_mut().clear());
+    f();
+    let calls = CALLS.with(|calls| calls.take());
+
+    for call in &calls {
+        if let Call::Crash(msg) = call {
+            return Err(msg.clone().into());
+        }
+    }
+
+    Ok(calls)
+}
+
+impl Dylib {
+    fn open(code: &[u8]) -> Result<Dylib, Error> {
+        puffin::profile_function!();
+
+        let copy = COPIES.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "vg-{}-{}{}",
+            std::process::id(),
+            copy,
+            std::env::consts::DLL_SUFFIX
+        ));
+        std::fs::write(&path, code)?;
+
+        let library = unsafe { Library::new(&path) }
+            .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+
+        let connect: extern "C" fn(extern "C" fn(u64, u64)) = symbol(&library, "__vg_connect")?;
+        let main: extern "C" fn() = symbol(&library, "__vg_main")?;
+        let exports = Exports {
+            tick: symbol(&library, "__vg_tick")?,
+            allocate: symbol(&library, "__vg_allocate")?,
+            save: symbol(&library, "__vg_save")?,
+            allocate_state: symbol(&library, "__vg_allocate_state")?,
+        };
+        connect(host_call);
+
+        debug!("Loaded native game from {}", path.display());
+
+        let dylib = Dylib {
+            exports,
+            library: Some(library),
+            path,
+        };
+
+        collect(main)?;
+        Ok(dylib)
+    }
+
+    fn write(&self, bytes: &[u8], allocate: extern "C" fn(u64) -> u64) {
+        let ptr = allocate(bytes.len() as u64) as *mut u8;
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len()) };
+    }
+}
+
+impl Runtime for Dylib {
+    fn load(code: &[u8]) -> Result<Self, Error> {
+        Dylib::open(code)
+    }
+
+    fn run_tick(&mut self) -> Result<Vec<Call>, Error> {
+        puffin::profile_function!();
+
+        collect(self.exports.tick)
+    }
+
+    fn send(&mut self, value: Response) -> Result<(), Error> {
+        puffin::profile_function!();
+
+        self.write(&value.serialize_bin(), self.exports.allocate);
+        Ok(())
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>, Error> {
+        Err("The native runtime cannot be serialized".into())
+    }
+
+    fn deserialize(_bytes: &[u8]) -> Result<Self, Error> {
+        Err("The native runtime cannot be serialized".into())
+    }
+
+    fn duplicate(&self) -> Result<Self, Error> {
+        Err("The native runtime cannot be duplicated".into())
+    }
+
+    fn can_duplicate() -> bool {
+        false
+    }
+
+    fn reload_from(&mut self, old: &mut Self) -> Result<bool, Error> {
+        let calls = collect(old.exports.save)?;
+        let state = calls.into_iter().find_map(|call| match call {
+            Call::State(state) => Some(state),
+            _ => None,
+        });
+
+        match state {
+            Some(state) => {
+                self.write(&state, self.exports.allocate_state);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Drop for Dylib {
+    fn drop(&mut self) {
+        // the file can only go once nothing has it open
+        drop(self.library.take());
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            trace!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -3,15 +3,25 @@ use vg_types::Call;
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
+#[cfg(feature = "dylib")]
+pub mod dylib;
+
 pub type Error = Box<dyn std::error::Error>;
 
+/// FNV-1a, stable across builds and platforms unlike `DefaultHasher`
+pub fn code_hash(code: &[u8]) -> u64 {
+    code.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 pub trait Runtime
 where
     Self: Sized,
 {
     fn load(code: &[u8]) -> Result<Self, Error>;
     fn run_tick(&mut self) -> Result<Vec<Call>, Error>;
-    fn send(&mut self, value: vg_types::Response);
+    fn send(&mut self, value: vg_types::Response) -> Result<(), Error>;
 
     fn serialize(&self) -> Result<Vec<u8>, Error>;
     fn deserialize(bytes: &[u8]) -> Result<Self, Error>;
@@ -20,4 +30,17 @@ where
         let bytes = self.serialize()?;
         Self::deserialize(&bytes)
     }
+
+    /// Whether the game can be copied with `duplicate`. Games that can't only
+    /// get interpolated frames and can't play online, both copy the game
+    /// every tick
+    fn can_duplicate() -> bool {
+        true
+    }
+
+    /// Carry game state over from a runtime running an older build of the same
+    /// game. Returns false if nothing was carried over and the game restarts
+    fn reload_from(&mut self, _old: &mut Self) -> Result<bool, Error> {
+        Ok(false)
+    }
 }
@@ -1,20 +1,254 @@
 use std::rc::Rc;
 use tracing::*;
 
-use super::{Error, Runtime};
+use super::{code_hash, Error, Runtime};
 
 use rust_wasm::*;
 use vg_types::*;
 
 pub struct Wasm {
+    code: Rc<[u8]>,
+    hash: u64,
+    layout: u64,
     instance: Rc<ModuleInst>,
     store: Store<Vec<Call>>,
 }
 
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Complete state of a running game, along with the code it was running
+#[derive(SerBin, DeBin)]
+struct Snapshot {
+    version: u32,
+    hash: u64,
+    code: Vec<u8>,
+    memories: Vec<Vec<u8>>,
+    // raw bits of every global, immutable ones included
+    globals: Vec<u64>,
+    tables: Vec<Vec<Option<u64>>>,
+}
+
+/// Turn a trap into an error, preferring the panic message the game sent
+fn trap_error(calls: Vec<Call>, err: rust_wasm::Error) -> Error {
+    let message = calls.into_iter().rev().find_map(|call| match call {
+        Call::Crash(msg) => Some(msg),
+        _ => None,
+    });
+
+    match message {
+        Some(msg) => msg.into(),
+        None => format!("Game trapped: {:?}", err).into(),
+    }
+}
+
+/// Hash of everything that decides where things live in linear memory. Static
+/// data includes vtables and therefore function indices, so two builds with the
+/// same layout can usually share a memory image. Function bodies are left out
+/// on purpose, tweaking what the code does keeps the game's state
+fn layout_hash(module: &ast::Module) -> u64 {
+    let mut bytes = vec![];
+    for memory in &module.memories {
+        bytes.extend_from_slice(format!("{:?}", memory.type_).as_bytes());
+    }
+
+    // the stack pointer and heap base live in globals
+    for global in &module.globals {
+        bytes.extend_from_slice(format!("{:?} {:?}", global.type_, global.value).as_bytes());
+    }
+
+    for elem in &module.elems {
+        bytes.extend_from_slice(format!("{:?}", elem.offset).as_bytes());
+        for index in &elem.init {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+
+    for data in &module.data {
+        bytes.extend_from_slice(&data.index.to_le_bytes());
+        bytes.extend_from_slice(format!("{:?}", data.offset).as_bytes());
+        bytes.extend_from_slice(&data.init);
+    }
+
+    code_hash(&bytes)
+}
+
+impl Wasm {
+    /// Hash identifying the module code this runtime was loaded from
+    pub fn code_hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Restore a snapshot of the same module into this runtime
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        puffin::profile_function!();
+
+        let snapshot =
+            Snapshot::deserialize_bin(bytes).map_err(|e| format!("Corrupt snapshot: {:?}", e))?;
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!("Unsupported snapshot version {}", snapshot.version).into());
+        }
+
+        if snapshot.hash != self.hash {
+            return Err(format!(
+                "Snapshot is of module {:016x}, but {:016x} is loaded",
+                snapshot.hash, self.hash
+            )
+            .into());
+        }
+
+        self.apply(&snapshot)
+    }
+
+    fn snapshot(&self) -> Result<Snapshot, Error> {
+        let memories = module_mems(&self.instance)
+            .iter()
+            .map(|mem| mem_data(&self.store, *mem).to_vec())
+            .collect();
+
+        let globals = module_globals(&self.instance)
+            .iter()
+            .map(|global| match read_global(&self.store, *global) {
+                values::Value::I32(v) => v as u64,
+                values::Value::I64(v) => v,
+                values::Value::F32(v) => v.to_bits() as u64,
+                values::Value::F64(v) => v.to_bits(),
+            })
+            .collect();
+
+        let mut tables = vec![];
+        for table in module_tables(&self.instance) {
+            let mut elems = vec![];
+            for i in 0..size_table(&self.store, *table) {
+                let func = read_table(&self.store, *table, i)
+                    .map_err(|e| format!("Failed to read table: {:?}", e))?;
+                elems.push(func.map(|func| func.addr() as u64));
+            }
+            tables.push(elems);
+        }
+
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            hash: self.hash,
+            code: self.code.to_vec(),
+            memories,
+            globals,
+            tables,
+        })
+    }
+
+    /// Overwrite memories, globals and tables with the ones in the snapshot
+    fn apply(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        let mems = module_mems(&self.instance);
+        let globals = module_globals(&self.instance);
+        let tables = module_tables(&self.instance);
+
+        if snapshot.memories.len() != mems.len()
+            || snapshot.globals.len() != globals.len()
+            || snapshot.tables.len() != tables.len()
+        {
+            return Err("Snapshot does not match the module layout".into());
+        }
+
+        for (mem, data) in mems.iter().zip(&snapshot.memories) {
+            if let Some(e) = write_mem_data(&mut self.store, *mem, data) {
+                return Err(format!("Failed to restore memory: {:?}", e).into());
+            }
+        }
+
+        for (global, bits) in globals.iter().zip(&snapshot.globals) {
+            // immutable globals are initialized identically on load
+            if !type_global(&self.store, *global).mutable {
+                continue;
+            }
+
+            let value = match read_global(&self.store, *global) {
+                values::Value::I32(_) => values::Value::I32(*bits as u32),
+                values::Value::I64(_) => values::Value::I64(*bits),
+                values::Value::F32(_) => values::Value::F32(f32::from_bits(*bits as u32)),
+                values::Value::F64(_) => values::Value::F64(f64::from_bits(*bits)),
+            };
+            write_global(&mut self.store, *global, value);
+        }
+
+        for (table, elems) in tables.iter().zip(&snapshot.tables) {
+            let size = size_table(&self.store, *table);
+            if elems.len() > size {
+                grow_table(&mut self.store, *table, elems.len() - size);
+            }
+
+            for (i, elem) in elems.iter().enumerate() {
+                let func = elem.map(|addr| FuncAddr::new(addr as usize));
+                if let Some(e) = write_table(&mut self.store, *table, i, func) {
+                    return Err(format!("Failed to restore table: {:?}", e).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask the game to save its state through its reload hook
+    fn save_state(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let func = match get_export(&self.instance, "__vg_save") {
+            Ok(ExternVal::Func(func)) => func,
+            _ => return Ok(None),
+        };
+
+        let mut calls = vec![];
+        invoke_func(&mut calls, &mut self.store, func, vec![])
+            .map_err(|e| format!("Saving game state trapped: {:?}", e))?;
+
+        Ok(calls.into_iter().find_map(|call| match call {
+            Call::State(state) => Some(state),
+            _ => None,
+        }))
+    }
+
+    /// Hand state saved by a previous build over to the game
+    fn restore_state(&mut self, state: &[u8]) -> Result<bool, Error> {
+        let func = match get_export(&self.instance, "__vg_allocate_state") {
+            Ok(ExternVal::Func(func)) => func,
+            _ => return Ok(false),
+        };
+
+        let ret = invoke_func(
+            &mut vec![],
+            &mut self.store,
+            func,
+            vec![values::Value::I64(state.len() as u64)],
+        )
+        .map_err(|e| format!("Allocating game state trapped: {:?}", e))?;
+
+        let ptr = match ret.get(0) {
+            Some(values::Value::I64(ptr)) => *ptr as usize,
+            e => return Err(format!("Invalid state allocation: {:?}", e).into()),
+        };
+
+        let mem = match get_export(&self.instance, "memory") {
+            Ok(ExternVal::Memory(mem)) => mem,
+            e => return Err(format!("Couldn't get memory: {:?}", e).into()),
+        };
+
+        for (off, byte) in state.iter().enumerate() {
+            if let Some(e) = write_mem(&mut self.store, mem, ptr + off, *byte) {
+                return Err(format!("Failed to write game state: {:?}", e).into());
+            }
+        }
+
+        Ok(true)
+    }
+}
+
 impl Runtime for Wasm {
     fn load(code: &[u8]) -> Result<Self, Error> {
+        let hash = code_hash(code);
+        debug!("Loading module {:016x}", hash);
+
         let mut store = init_store();
-        let module = decode_module(std::io::Cursor::new(code)).unwrap();
+        let module = decode_module(std::io::Cursor::new(code))
+            .map_err(|e| format!("Failed to decode module: {:?}", e))?;
+        let layout = layout_hash(&module);
 
         let call_type = types::Func {
             args: vec![types::I64, types::I64],
@@ -33,12 +267,20 @@ impl Runtime for Wasm {
                 return None;
             };
 
-            let bytes = &mem[*ptr as usize..][..*len as usize];
-            let call = Call::deserialize_bin(bytes).unwrap();
+            // a bad call traps, which ends the tick with an error
+            let bytes = match mem
+                .get(*ptr as usize..)
+                .and_then(|mem| mem.get(..*len as usize))
+            {
+                Some(bytes) => bytes,
+                None => return Some(format!("Call out of bounds at {}+{}", ptr, len)),
+            };
 
-            engine.push(call);
+            match Call::deserialize_bin(bytes) {
+                Ok(call) => engine.push(call),
+                Err(e) => return Some(format!("Malformed call: {:?}", e)),
+            }
 
-            // TODO: Pass calls back to engine
             None
         };
 
@@ -138,24 +380,29 @@ impl Runtime for Wasm {
                 ExternVal::Func(environ_get),
             ],
         )
-        .unwrap();
+        .map_err(|e| format!("Failed to instantiate module: {:?}", e))?;
 
         let func = match get_export(&instance, "main") {
             Ok(ExternVal::Func(func)) => func,
-            e => {
-                panic!("Couldn't get main: {:?}", e)
-            }
+            e => return Err(format!("Couldn't get main: {:?}", e).into()),
         };
 
+        let mut calls = vec![];
         invoke_func(
-            &mut vec![],
+            &mut calls,
             &mut store,
             func,
             vec![values::Value::I32(0), values::Value::I32(0)],
         )
-        .unwrap();
-
-        Ok(Wasm { instance, store })
+        .map_err(|e| trap_error(calls, e))?;
+
+        Ok(Wasm {
+            code: code.into(),
+            hash,
+            layout,
+            instance,
+            store,
+        })
     }
 
     fn run_tick(&mut self) -> Result<Vec<Call>, Error> {
@@ -163,20 +410,20 @@ impl Runtime for Wasm {
 
         let func = match get_export(&self.instance, "__vg_tick") {
             Ok(ExternVal::Func(func)) => func,
-            e => {
-                panic!("Couldn't get __vg_tick: {:?}", e)
-            }
+            e => return Err(format!("Couldn't get __vg_tick: {:?}", e).into()),
         };
 
         // self.engine.set(Some(engine));
         let mut calls = vec![];
-        invoke_func(&mut calls, &mut self.store, func, vec![]).unwrap();
+        if let Err(e) = invoke_func(&mut calls, &mut self.store, func, vec![]) {
+            return Err(trap_error(calls, e));
+        }
         // self.engine.set(None);
 
         Ok(calls)
     }
 
-    fn send(&mut self, value: vg_types::Response) {
+    fn send(&mut self, value: vg_types::Response) -> Result<(), Error> {
         puffin::profile_function!();
 
         trace!("Sending {:#?} to runtime", value);
@@ -184,54 +431,55 @@ impl Runtime for Wasm {
 
         let func = match get_export(&self.instance, "__vg_allocate") {
             Ok(ExternVal::Func(func)) => func,
-            e => {
-                panic!("Couldn't get __vg_allocate: {:?}", e)
-            }
+            e => return Err(format!("Couldn't get __vg_allocate: {:?}", e).into()),
         };
 
-        let len = bytes.len();
-
-        let ptr = invoke_func(
-            &mut vec![],
+        let mut calls = vec![];
+        let ptr = match invoke_func(
+            &mut calls,
             &mut self.store,
             func,
-            vec![values::Value::I64(len as u64)],
-        )
-        .unwrap()[0];
+            vec![values::Value::I64(bytes.len() as u64)],
+        ) {
+            Ok(results) => results.get(0).cloned(),
+            Err(e) => return Err(trap_error(calls, e)),
+        };
 
-        let ptr = if let values::Value::I64(ptr) = ptr {
-            ptr as usize
-        } else {
-            panic!()
+        let ptr = match ptr {
+            Some(values::Value::I64(ptr)) => ptr as usize,
+            ptr => return Err(format!("__vg_allocate returned {:?}", ptr).into()),
         };
 
         let mem = match get_export(&self.instance, "memory") {
             Ok(ExternVal::Memory(mem)) => mem,
-            e => {
-                panic!("Couldn't get __vg_allocate: {:?}", e)
-            }
+            e => return Err(format!("Couldn't get memory: {:?}", e).into()),
         };
 
         for (off, byte) in bytes.iter().enumerate() {
-            assert!(rust_wasm::write_mem(&mut self.store, mem, ptr + off, *byte).is_none());
+            if let Some(e) = write_mem(&mut self.store, mem, ptr + off, *byte) {
+                return Err(format!("Failed to write response: {:?}", e).into());
+            }
         }
 
-        // let func = match get_export(&self.instance, "__vg_consume") {
-        //     Ok(ExternVal::Func(func)) => func,
-        //     e => {
-        //         panic!("Couldn't get __vg_consume: {:?}", e)
-        //     }
-        // };
-
-        // invoke_func(&mut self.store, func, vec![]).unwrap();
+        Ok(())
     }
 
     fn serialize(&self) -> Result<Vec<u8>, Error> {
-        todo!()
+        puffin::profile_function!();
+
+        Ok(self.snapshot()?.serialize_bin())
     }
 
-    fn deserialize(_bytes: &[u8]) -> Result<Self, Error> {
-        todo!()
+    fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        puffin::profile_function!();
+
+        let snapshot =
+            Snapshot::deserialize_bin(bytes).map_err(|e| format!("Corrupt snapshot: {:?}", e))?;
+
+        // Start from a fresh instance of the same code, then overwrite its state
+        let mut wasm = Wasm::load(&snapshot.code)?;
+        wasm.restore(bytes)?;
+        Ok(wasm)
     }
 
     fn duplicate(&self) -> Result<Self, Error> {
@@ -240,6 +488,265 @@ impl Runtime for Wasm {
         let instance = Rc::new(ModuleInst::clone(&self.instance));
         let store = self.store.clone();
 
-        Ok(Wasm { instance, store })
+        Ok(Wasm {
+            code: Rc::clone(&self.code),
+            hash: self.hash,
+            layout: self.layout,
+            instance,
+            store,
+        })
+    }
+
+    fn reload_from(&mut self, old: &mut Self) -> Result<bool, Error> {
+        puffin::profile_function!();
+
+        // the game knows best how to carry its state over
+        if let Some(state) = old.save_state()? {
+            if self.restore_state(&state)? {
+                debug!(
+                    "Game state carried over by the game ({} bytes)",
+                    state.len()
+                );
+                return Ok(true);
+            }
+        }
+
+        if old.layout == self.layout {
+            let snapshot = old.snapshot()?;
+            self.apply(&snapshot)?;
+            debug!("Memory layout unchanged, game state carried over as is");
+            return Ok(true);
+        }
+
+        debug!("Memory layout changed and the game saved no state, game state lost");
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const I32: u8 = 0x7f;
+    const I64: u8 = 0x7e;
+    const END: u8 = 0x0b;
+
+    /// Imports every game has, in the order `load` provides them, with the
+    /// index of their type in `module`
+    const IMPORTS: &[(&str, u8)] = &[
+        ("call", 0),
+        ("fd_write", 1),
+        ("random_get", 2),
+        ("proc_exit", 3),
+        ("environ_sizes_get", 2),
+        ("environ_get", 2),
+    ];
+
+    fn uleb(mut n: u64, out: &mut Vec<u8>) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                return out.push(byte);
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn sleb(mut n: i64, out: &mut Vec<u8>) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+                return out.push(byte);
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    /// Length prefixed bytes, like vectors and names are encoded
+    fn sized(bytes: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        uleb(bytes.len() as u64, &mut out);
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    fn vec(items: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = vec![];
+        uleb(items.len() as u64, &mut out);
+        for item in items {
+            out.extend_from_slice(item);
+        }
+        out
+    }
+
+    fn i32_const(n: i64) -> Vec<u8> {
+        let mut out = vec![0x41];
+        sleb(n, &mut out);
+        out
+    }
+
+    fn i64_const(n: i64) -> Vec<u8> {
+        let mut out = vec![0x42];
+        sleb(n, &mut out);
+        out
+    }
+
+    fn func_type(params: &[u8], results: &[u8]) -> Vec<u8> {
+        [vec![0x60], sized(params), sized(results)].concat()
+    }
+
+    /// A game that counts its ticks at `counter` in memory and the bytes it
+    /// was sent in a global, and tells both with `Call::State` every tick
+    fn module(counter: i64) -> Vec<u8> {
+        let call = Call::State(vec![0; 8]).serialize_bin();
+        let payload = call.len() as i64 - 8;
+        let funcs = IMPORTS.len() as u8;
+
+        let types = vec![
+            func_type(&[I64, I64], &[]),
+            func_type(&[I32, I32, I32, I32], &[I32]),
+            func_type(&[I32, I32], &[I32]),
+            func_type(&[I32], &[]),
+            func_type(&[], &[]),
+            func_type(&[I64], &[I64]),
+        ];
+        let imports: Vec<Vec<u8>> = IMPORTS
+            .iter()
+            .map(|(field, type_)| {
+                [sized(b"env"), sized(field.as_bytes()), vec![0, *type_]].concat()
+            })
+            .collect();
+
+        let (load, store, add) = (vec![0x28, 2, 0], vec![0x36, 2, 0], vec![0x6a]);
+        let (global_get, global_set) = (vec![0x23, 0], vec![0x24, 0]);
+        // (i32, i32) -> i32, returns 0
+        let main = i32_const(0);
+        // mem[counter] += 1, then call with mem[counter] and the global
+        let tick = [
+            i32_const(counter),
+            i32_const(counter),
+            load.clone(),
+            i32_const(1),
+            add.clone(),
+            store.clone(),
+            i32_const(payload),
+            i32_const(counter),
+            load,
+            store.clone(),
+            i32_const(payload + 4),
+            global_get.clone(),
+            store,
+            i64_const(0),
+            i64_const(call.len() as i64),
+            vec![0x10, 0],
+        ]
+        .concat();
+        // global += len, then hand out the same buffer every time
+        let allocate = [
+            global_get,
+            vec![0x20, 0, 0xa7],
+            add,
+            global_set,
+            i64_const(1024),
+        ]
+        .concat();
+        let bodies: Vec<Vec<u8>> = [main, tick, allocate]
+            .iter()
+            .map(|code| sized(&[vec![0], code.clone(), vec![END]].concat()))
+            .collect();
+
+        let at_zero = [i32_const(0), vec![END]].concat();
+        let exports = vec![
+            [sized(b"memory"), vec![2, 0]].concat(),
+            [sized(b"main"), vec![0, funcs]].concat(),
+            [sized(b"__vg_tick"), vec![0, funcs + 1]].concat(),
+            [sized(b"__vg_allocate"), vec![0, funcs + 2]].concat(),
+        ];
+
+        let sections = vec![
+            (1, vec(&types)),
+            (2, vec(&imports)),
+            (3, vec(&[vec![2], vec![4], vec![5]])),
+            // a table of two, holding the tick function first
+            (4, vec(&[vec![0x70, 0, 2]])),
+            (5, vec(&[vec![0, 1]])),
+            (6, vec(&[[vec![I32, 1], at_zero.clone()].concat()])),
+            (7, vec(&exports)),
+            (
+                9,
+                vec(&[[vec![0], at_zero.clone(), vec![1, funcs + 1]].concat()]),
+            ),
+            (10, vec(&bodies)),
+            (11, vec(&[[vec![0], at_zero, sized(&call)].concat()])),
+        ];
+
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        for (id, content) in sections {
+            module.push(id);
+            module.extend(sized(&content));
+        }
+        module
+    }
+
+    /// Ticks and bytes received, as the game reports them
+    fn state(calls: &[Call]) -> (u32, u32) {
+        match calls {
+            [Call::State(payload)] => {
+                let word = |i: usize| {
+                    u32::from_le_bytes([payload[i], payload[i + 1], payload[i + 2], payload[i + 3]])
+                };
+                (word(0), word(4))
+            }
+            calls => panic!("unexpected calls {:?}", calls),
+        }
+    }
+
+    fn play(wasm: &mut Wasm, ticks: usize) -> Vec<(u32, u32)> {
+        (0..ticks)
+            .map(|tick| {
+                wasm.send(Response::Time(tick as f64)).unwrap();
+                state(&wasm.run_tick().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn snapshots_restore_running_games() {
+        let code = module(512);
+        let sent = Response::Time(0.0).serialize_bin().len() as u32;
+
+        let mut wasm = Wasm::load(&code).unwrap();
+        assert_eq!(
+            play(&mut wasm, 3),
+            vec![(1, sent), (2, sent * 2), (3, sent * 3)]
+        );
+
+        let snapshot = wasm.serialize().unwrap();
+        assert_ne!(Wasm::load(&code).unwrap().serialize().unwrap(), snapshot);
+
+        // memory, globals and tables come back as they were
+        let mut restored = Wasm::deserialize(&snapshot).unwrap();
+        assert_eq!(restored.serialize().unwrap(), snapshot);
+        assert_eq!(restored.code_hash(), wasm.code_hash());
+
+        // and the game goes on from there
+        let expected = play(&mut wasm, 5);
+        assert_eq!(expected[0], (4, sent * 4));
+        assert_eq!(play(&mut restored, 5), expected);
+
+        // restoring rewinds a game that kept going
+        wasm.restore(&snapshot).unwrap();
+        assert_eq!(play(&mut wasm, 5), expected);
+    }
+
+    #[test]
+    fn snapshots_only_restore_into_the_same_module() {
+        let snapshot = Wasm::load(&module(512)).unwrap().serialize().unwrap();
+
+        let mut other = Wasm::load(&module(256)).unwrap();
+        assert!(other.restore(&snapshot).is_err());
+        assert!(Wasm::deserialize(&snapshot[..snapshot.len() / 2]).is_err());
     }
 }
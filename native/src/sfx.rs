@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
         mpsc::{self, Receiver, Sender},
         Arc,
@@ -13,14 +13,16 @@ use cpal::{
     BufferSize, Stream, StreamConfig,
 };
 use dashmap::DashMap;
+use futures::compat::{Compat01As03, Stream01CompatExt};
 use lewton::{
-    header::{read_header_comment, read_header_ident, read_header_setup},
-    inside_ogg::async_api::OggStreamReader,
+    header::{read_header_comment, read_header_ident, read_header_setup, HeaderReadError},
+    inside_ogg::{async_api::OggStreamReader, OggStreamReader as SyncOggStreamReader},
+    VorbisError,
 };
-use oddio::{Handle, Mixer, Spatial, SpatialScene, Stop};
+use oddio::{Frames, Handle, Mixer, Spatial, SpatialScene, Stop};
 use tracing::{debug, error, warn};
 
-use crate::assets::Cache;
+use crate::assets::{Assets, Cache, CacheRead};
 
 pub struct Sfx {
     scene: Handle<Mixer<[f32; 2]>>,
@@ -29,8 +31,13 @@ pub struct Sfx {
     dead_sounds: Receiver<Handle<Stop<oddio::Stream<[f32; 2]>>>>,
     active_streams: usize,
     hack_bgm: bool,
+    decoded: HashMap<PathBuf, Arc<Frames<[f32; 2]>>>,
 }
 
+// Sounds up to this size (encoded) are decoded once and played from memory,
+// anything bigger is streamed from disk
+pub const DECODE_LIMIT: usize = 256 * 1024;
+
 impl Sfx {
     pub fn new() -> Sfx {
         let host = cpal::default_host();
@@ -75,37 +82,82 @@ impl Sfx {
             dead_sounds,
             active_streams: 0,
             hack_bgm: false,
+            decoded: HashMap::new(),
+        }
+    }
+
+    /// Drop decoded sounds of changed assets, streamed ones read the new data
+    /// on their own
+    pub fn forget(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            self.decoded.remove(path);
+        }
+    }
+
+    /// Whether a sound is already decoded and ready to play
+    pub fn has_sound(&self, path: &Path) -> bool {
+        self.decoded.contains_key(path)
+    }
+
+    /// Keep a sound decoded ahead of time
+    pub fn preloaded(&mut self, path: PathBuf, frames: Arc<Frames<[f32; 2]>>) {
+        self.decoded.insert(path, frames);
+    }
+
+    /// Play a sound. Sounds that fail to decode are failed in `assets` under
+    /// their name and stay silent
+    pub async fn play_sound(
+        &mut self,
+        assets: &Assets,
+        name: &str,
+        asset: Arc<Cache>,
+        looping: bool,
+    ) {
+        let result = if asset.len <= DECODE_LIMIT {
+            self.play_decoded(asset, looping).await
+        } else {
+            self.play_streamed(asset, looping).await
+        };
+
+        if let Err(e) = result {
+            assets.fail(name, e);
         }
     }
 
-    pub async fn play_sound(&mut self, asset: Arc<Cache>, looping: bool) {
+    async fn play_decoded(&mut self, asset: Arc<Cache>, looping: bool) -> Result<(), String> {
+        puffin::profile_function!();
+
+        if !self.decoded.contains_key(&asset.path) {
+            let bytes = asset.load_all().await.map_err(|e| e.to_string())?;
+            let frames = decode_all(bytes).map_err(|e| format!("Vorbis decode error: {:?}", e))?;
+
+            self.decoded.insert(asset.path.clone(), frames);
+            debug!("Sound decoded");
+        }
+
+        let frames = Arc::clone(self.decoded.get(&asset.path).unwrap());
+        debug!("Playing {} from memory", asset.path.display());
+
+        if looping {
+            self.scene.control().play(oddio::Cycle::new(frames));
+        } else {
+            self.scene.control().play(oddio::FramesSignal::from(frames));
+        }
+
+        Ok(())
+    }
+
+    async fn play_streamed(&mut self, asset: Arc<Cache>, looping: bool) -> Result<(), String> {
         if asset.path.ends_with("bgm.ogg") {
             if self.hack_bgm {
-                return;
+                return Ok(());
             } else {
                 self.hack_bgm = true;
             }
         }
 
-        use futures::{compat::Stream01CompatExt, StreamExt};
-        let mut packet_reader =
-            ogg::reading::async_api::PacketReader::new(asset.start_read().await).compat();
-
-        let ident = read_header_ident(&packet_reader.next().await.unwrap().unwrap().data).unwrap();
-        let comment =
-            read_header_comment(&packet_reader.next().await.unwrap().unwrap().data).unwrap();
-        let setup = read_header_setup(
-            &packet_reader.next().await.unwrap().unwrap().data,
-            ident.audio_channels,
-            (ident.blocksize_0, ident.blocksize_1),
-        )
-        .unwrap();
-
-        let sample_rate = ident.audio_sample_rate;
-
-        let mut decoder =
-            OggStreamReader::from_pck_rdr(packet_reader.into_inner(), (ident, comment, setup))
-                .compat();
+        use futures::StreamExt;
+        let (mut decoder, sample_rate) = open_stream(&asset).await?;
 
         let signal = oddio::Stream::<[f32; 2]>::new(sample_rate, 1024);
         debug!("Playing {} at {}hz", asset.path.display(), sample_rate);
@@ -143,30 +195,13 @@ impl Sfx {
                     None => {
                         if looping {
                             debug!("Restarting loop");
-                            let mut packet_reader = ogg::reading::async_api::PacketReader::new(
-                                asset.start_read().await,
-                            )
-                            .compat();
-
-                            let ident = read_header_ident(
-                                &packet_reader.next().await.unwrap().unwrap().data,
-                            )
-                            .unwrap();
-                            let comment = read_header_comment(
-                                &packet_reader.next().await.unwrap().unwrap().data,
-                            )
-                            .unwrap();
-                            let setup = read_header_setup(
-                                &packet_reader.next().await.unwrap().unwrap().data,
-                                ident.audio_channels,
-                                (ident.blocksize_0, ident.blocksize_1),
-                            )
-                            .unwrap();
-                            decoder = OggStreamReader::from_pck_rdr(
-                                packet_reader.into_inner(),
-                                (ident, comment, setup),
-                            )
-                            .compat();
+                            match open_stream(&asset).await {
+                                Ok((restarted, _)) => decoder = restarted,
+                                Err(e) => {
+                                    error!("Failed to restart {}: {}", asset.path.display(), e);
+                                    break;
+                                }
+                            }
                         } else {
                             debug!("Done with decoding");
                             break;
@@ -217,5 +252,71 @@ impl Sfx {
 
             let _ = dead_sound_tx.send(handle);
         });
+
+        Ok(())
     }
 }
+
+type StreamDecoder = Compat01As03<OggStreamReader<CacheRead>>;
+
+/// Read the headers of a streamed sound, returning a decoder for the rest and
+/// its sample rate
+async fn open_stream(asset: &Cache) -> Result<(StreamDecoder, u32), String> {
+    use futures::StreamExt;
+    let mut packet_reader = ogg::reading::async_api::PacketReader::new(
+        asset.start_read().await.map_err(|e| e.to_string())?,
+    )
+    .compat();
+
+    let mut headers = vec![];
+    for _ in 0..3 {
+        match packet_reader.next().await {
+            Some(Ok(packet)) => headers.push(packet.data),
+            Some(Err(e)) => return Err(format!("Ogg read error: {}", e)),
+            None => return Err("Vorbis headers missing".into()),
+        }
+    }
+
+    let header_err = |e: HeaderReadError| format!("Vorbis header error: {:?}", e);
+    let ident = read_header_ident(&headers[0]).map_err(header_err)?;
+    let comment = read_header_comment(&headers[1]).map_err(header_err)?;
+    let setup = read_header_setup(
+        &headers[2],
+        ident.audio_channels,
+        (ident.blocksize_0, ident.blocksize_1),
+    )
+    .map_err(header_err)?;
+
+    let sample_rate = ident.audio_sample_rate;
+    let decoder =
+        OggStreamReader::from_pck_rdr(packet_reader.into_inner(), (ident, comment, setup)).compat();
+
+    Ok((decoder, sample_rate))
+}
+
+// Decode a whole vorbis file into stereo frames
+pub fn decode_all(bytes: Vec<u8>) -> Result<Arc<Frames<[f32; 2]>>, VorbisError> {
+    let mut reader = SyncOggStreamReader::new(std::io::Cursor::new(bytes))?;
+
+    let mut samples = vec![];
+    while let Some(packet) = reader.read_dec_packet()? {
+        let left = match packet.get(0) {
+            Some(left) => left,
+            None => continue,
+        };
+        // mono files play the same channel on both sides
+        let right = packet.get(1).unwrap_or(left);
+
+        for (l, r) in left.iter().zip(right) {
+            samples.push([
+                *l as f32 / std::i16::MAX as f32,
+                *r as f32 / std::i16::MAX as f32,
+            ]);
+        }
+    }
+
+    Ok(Frames::from_slice(
+        reader.ident_hdr.audio_sample_rate,
+        &samples,
+    ))
+}
@@ -0,0 +1,386 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use glam::{Vec2, Vec3};
+use image::{Rgba, RgbaImage};
+use tracing::{debug, warn};
+use vg_types::{DrawCall, Filter, Sampling, Transform, Wrap};
+
+use crate::{
+    assets::{Assets, Cache},
+    gfx,
+    runtime::Error,
+    tilemap::Map,
+};
+
+/// Same camera as the GPU renderer: 90 degree vertical fov, 5 units back
+const CAMERA: Vec3 = glam::const_vec3!([0.0, 0.0, -5.0]);
+const NEAR: f32 = 0.1;
+const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Corners of the sprite quad with their texture coordinates
+const QUAD: [([f32; 3], [f32; 2]); 4] = [
+    ([-0.5, -0.5, 0.0], [0.0, 1.0]),
+    ([0.5, -0.5, 0.0], [1.0, 1.0]),
+    ([0.5, 0.5, 0.0], [1.0, 0.0]),
+    ([-0.5, 0.5, 0.0], [0.0, 0.0]),
+];
+
+/// Projected vertex, with everything that is interpolated divided by depth
+#[derive(Clone, Copy)]
+struct Vertex {
+    screen: Vec2,
+    inv_z: f32,
+    uv: Vec2,
+}
+
+/// Rasterizes draw lists on the CPU, so frames can be checked against known
+/// good images on machines without a GPU. Follows the camera and transforms of
+/// the GPU renderer, but is not expected to match it pixel for pixel
+pub struct Software {
+    width: u32,
+    height: u32,
+    textures: HashMap<String, RgbaImage>,
+}
+
+impl Software {
+    pub fn new(width: u32, height: u32) -> Software {
+        Software {
+            width,
+            height,
+            textures: HashMap::new(),
+        }
+    }
+
+    /// Change the size of the frames rendered from now on
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+    }
+
+    /// Read every texture again on the next render
+    pub fn forget(&mut self) {
+        self.textures.clear();
+    }
+
+    /// Draw a single frame, tile maps first and then sprites in the order
+    /// the draws were made. Like on the GPU, sprites that can't be loaded are
+    /// failed in `assets` and drawn as a placeholder
+    pub async fn render<'a>(
+        &mut self,
+        assets: &Assets,
+        draws: impl IntoIterator<Item = &'a DrawCall>,
+        tilemaps: &[(Arc<Map>, Transform)],
+    ) -> RgbaImage {
+        puffin::profile_function!();
+
+        let mut frame = RgbaImage::from_pixel(self.width, self.height, CLEAR);
+        let mut depth = vec![0.0; (self.width * self.height) as usize];
+
+        for (map, trans) in tilemaps {
+            // exactly what the GPU renderer places objects with
+            let mat = gfx::trans2mat(*trans).transform;
+
+            // tilesets that failed to load leave their tiles out
+            for batch in map.batches() {
+                let image = match &map.images[batch.tileset] {
+                    Some(image) => image,
+                    None => continue,
+                };
+                let tileset = &map.tilesets[batch.tileset];
+                let size = [tileset.tile_width, tileset.tile_height];
+                let sampler = Sampler {
+                    image,
+                    nearest: gfx::auto_filter(size) == Filter::Nearest,
+                    wrap: Wrap::Repeat,
+                    opacity: batch.opacity,
+                };
+
+                let corners = batch.positions.iter().zip(&batch.uvs);
+                let corners: Vec<_> = corners
+                    .map(|(pos, uv)| (mat.transform_point3((*pos).into()), (*uv).into()))
+                    .collect();
+                for quad in corners.chunks_exact(4) {
+                    self.quad(&mut frame, &mut depth, &sampler, quad);
+                }
+            }
+        }
+
+        for draw in draws {
+            self.load(assets, &draw.asset).await;
+            let texture = &self.textures[&draw.asset];
+            let sampler = Sampler::new(texture, draw.sampling);
+
+            let mat = gfx::trans2mat(draw.trans).transform;
+            let quad: Vec<_> = QUAD
+                .iter()
+                .map(|(pos, uv)| (mat.transform_point3((*pos).into()), (*uv).into()))
+                .collect();
+            self.quad(&mut frame, &mut depth, &sampler, &quad);
+        }
+
+        frame
+    }
+
+    /// Fill in a quad given by its corners in world space, counter clockwise
+    fn quad(
+        &self,
+        frame: &mut RgbaImage,
+        depth: &mut [f32],
+        sampler: &Sampler,
+        corners: &[(Vec3, Vec2)],
+    ) {
+        let vertices: Option<Vec<Vertex>> = corners
+            .iter()
+            .map(|(world, uv)| self.project(*world, *uv))
+            .collect();
+
+        // no clipping, quads reaching behind the camera are skipped
+        let vertices = match vertices {
+            Some(vertices) => vertices,
+            None => return,
+        };
+
+        for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+            let tri = [vertices[a], vertices[b], vertices[c]];
+            raster(frame, depth, sampler, tri);
+        }
+    }
+
+    async fn load(&mut self, assets: &Assets, asset: &str) {
+        if self.textures.contains_key(asset) {
+            return;
+        }
+
+        let image = match assets.get(asset).await {
+            Some(cache) => match decode(&cache).await {
+                Ok(image) => {
+                    debug!("Loaded texture {}", cache.path.display());
+                    image
+                }
+                Err(e) => {
+                    assets.fail(asset, e);
+                    gfx::placeholder()
+                }
+            },
+            None => gfx::placeholder(),
+        };
+
+        self.textures.insert(asset.into(), image);
+    }
+
+    fn project(&self, world: Vec3, uv: Vec2) -> Option<Vertex> {
+        let view = world - CAMERA;
+        if view.z < NEAR {
+            return None;
+        }
+
+        // tan(90° / 2) is 1, so only the aspect ratio is left
+        let aspect = self.width as f32 / self.height as f32;
+        let ndc = Vec2::new(view.x / (view.z * aspect), view.y / view.z);
+        let screen = Vec2::new(
+            (ndc.x + 1.0) * 0.5 * self.width as f32,
+            (1.0 - ndc.y) * 0.5 * self.height as f32,
+        );
+
+        let inv_z = 1.0 / view.z;
+        Some(Vertex {
+            screen,
+            inv_z,
+            uv: uv * inv_z,
+        })
+    }
+}
+
+async fn decode(cache: &Cache) -> Result<RgbaImage, String> {
+    let bytes = cache.load_all().await.map_err(|e| e.to_string())?;
+    let image = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+    Ok(image.to_rgba8())
+}
+
+fn raster(frame: &mut RgbaImage, depth: &mut [f32], sampler: &Sampler, tri: [Vertex; 3]) {
+    let [a, b, c] = tri;
+    let area = edge(a.screen, b.screen, c.screen);
+    if area.abs() < f32::EPSILON {
+        return;
+    }
+
+    let (width, height) = frame.dimensions();
+    let min = a.screen.min(b.screen).min(c.screen).max(Vec2::ZERO);
+    let max = a.screen.max(b.screen).max(c.screen);
+    let (x1, y1) = (
+        (max.x.ceil() as u32).min(width),
+        (max.y.ceil() as u32).min(height),
+    );
+
+    for y in min.y as u32..y1 {
+        for x in min.x as u32..x1 {
+            let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+
+            // quads are double sided, so accept either winding
+            let wa = edge(b.screen, c.screen, p) / area;
+            let wb = edge(c.screen, a.screen, p) / area;
+            let wc = edge(a.screen, b.screen, p) / area;
+            if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                continue;
+            }
+
+            let inv_z = wa * a.inv_z + wb * b.inv_z + wc * c.inv_z;
+            let index = (y * width + x) as usize;
+            // later draws win ties, like painting over
+            if inv_z < depth[index] {
+                continue;
+            }
+
+            let uv = (a.uv * wa + b.uv * wb + c.uv * wc) / inv_z;
+            let mut texel = sampler.sample(uv);
+            texel[3] *= sampler.opacity;
+            if texel[3] <= 0.0 {
+                continue;
+            }
+
+            depth[index] = inv_z;
+            blend(frame.get_pixel_mut(x, y), texel);
+        }
+    }
+}
+
+fn edge(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// A texture along with how a single draw samples it. Mipmaps are not
+/// emulated, the full size image is always sampled
+struct Sampler<'a> {
+    image: &'a RgbaImage,
+    nearest: bool,
+    wrap: Wrap,
+    opacity: f32,
+}
+
+impl<'a> Sampler<'a> {
+    fn new(image: &'a RgbaImage, sampling: Sampling) -> Sampler<'a> {
+        let size = [image.width(), image.height()];
+        let filter = sampling.filter.unwrap_or_else(|| gfx::auto_filter(size));
+
+        Sampler {
+            image,
+            nearest: filter == Filter::Nearest,
+            wrap: sampling.wrap,
+            opacity: 1.0,
+        }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> [f32; 4] {
+        let (width, height) = self.image.dimensions();
+        let x = wrap(x, width, self.wrap);
+        let y = wrap(y, height, self.wrap);
+
+        let Rgba(px) = *self.image.get_pixel(x, y);
+        px.map(|c| c as f32)
+    }
+
+    fn sample(&self, uv: Vec2) -> [f32; 4] {
+        let (width, height) = self.image.dimensions();
+        let x = uv.x * width as f32 - 0.5;
+        let y = uv.y * height as f32 - 0.5;
+
+        if self.nearest {
+            return self.texel(x.round() as i64, y.round() as i64);
+        }
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let mut out = [0.0; 4];
+        let corners = [
+            (self.texel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+            (self.texel(x0 + 1, y0), fx * (1.0 - fy)),
+            (self.texel(x0, y0 + 1), (1.0 - fx) * fy),
+            (self.texel(x0 + 1, y0 + 1), fx * fy),
+        ];
+        for (px, weight) in corners.iter() {
+            for (out, c) in out.iter_mut().zip(px) {
+                *out += c * weight;
+            }
+        }
+
+        out
+    }
+}
+
+fn wrap(i: i64, len: u32, wrap: Wrap) -> u32 {
+    let len = len as i64;
+    let i = match wrap {
+        Wrap::Repeat => i.rem_euclid(len),
+        Wrap::Clamp => i.clamp(0, len - 1),
+        Wrap::Mirror => {
+            let i = i.rem_euclid(len * 2);
+            if i < len {
+                i
+            } else {
+                len * 2 - 1 - i
+            }
+        }
+    };
+
+    i as u32
+}
+
+fn blend(dst: &mut Rgba<u8>, src: [f32; 4]) {
+    let alpha = src[3] / 255.0;
+    for (dst, src) in dst.0.iter_mut().zip(&src[..3]) {
+        *dst = (src * alpha + *dst as f32 * (1.0 - alpha)).round() as u8;
+    }
+    dst.0[3] = 255;
+}
+
+/// Check a frame against a known good image. Setting `VG_BLESS` writes the
+/// frame as the new known good image instead, and a mismatching frame is saved
+/// next to the golden one for inspection
+pub fn compare(frame: &RgbaImage, golden: &Path, tolerance: u8) -> Result<(), Error> {
+    if std::env::var_os("VG_BLESS").is_some() {
+        warn!("Blessing {}", golden.display());
+        frame.save(golden)?;
+        return Ok(());
+    }
+
+    let expected = image::open(golden)
+        .map_err(|e| format!("Failed to open {}: {}", golden.display(), e))?
+        .to_rgba8();
+
+    let actual = golden.with_extension("actual.png");
+    if expected.dimensions() != frame.dimensions() {
+        frame.save(&actual)?;
+        return Err(format!(
+            "Frame is {:?} but {} is {:?}",
+            frame.dimensions(),
+            golden.display(),
+            expected.dimensions()
+        )
+        .into());
+    }
+
+    let differing = expected
+        .pixels()
+        .zip(frame.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(&b.0)
+                .any(|(a, b)| a.max(b) - a.min(b) > tolerance)
+        })
+        .count();
+
+    if differing > 0 {
+        frame.save(&actual)?;
+        return Err(format!(
+            "{} pixels differ from {}, see {}",
+            differing,
+            golden.display(),
+            actual.display()
+        )
+        .into());
+    }
+
+    Ok(())
+}
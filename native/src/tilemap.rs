@@ -0,0 +1,1129 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use image::RgbaImage;
+use serde_json::Value;
+use tracing::{debug, warn};
+use vg_types::{MapData, MapObject, ObjectLayer, Property, PropertyValue, TileLayer};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::assets::Assets;
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+/// Everything but the flip flags
+const GID_MASK: u32 = 0x0fff_ffff;
+
+/// How far apart layers are drawn, later layers in front
+const LAYER_DEPTH: f32 = 0.001;
+
+/// A Tiled map ready to be drawn
+pub struct Map {
+    /// Sent to the game as is
+    pub data: MapData,
+    /// Where the map was read from, identifies its meshes
+    pub path: PathBuf,
+    /// Every file the map was read from, including tilesets and their images
+    pub sources: Vec<PathBuf>,
+    pub tilesets: Vec<Tileset>,
+    /// Decoded tileset images, `None` where they failed to load
+    pub images: Vec<Option<RgbaImage>>,
+    /// Tiles of every layer along with their flip flags
+    layers: Vec<Vec<u32>>,
+    /// How each layer is drawn
+    styles: Vec<Style>,
+}
+
+/// How a layer is drawn, including what it inherits from the groups it is in
+#[derive(Clone, Copy)]
+struct Style {
+    visible: bool,
+    opacity: f32,
+    /// In pixels, going right and down
+    offset: [f32; 2],
+}
+
+impl Style {
+    const ROOT: Style = Style {
+        visible: true,
+        opacity: 1.0,
+        offset: [0.0, 0.0],
+    };
+
+    /// Style of a layer inside this one
+    fn nest(self, visible: bool, opacity: f32, offset: [f32; 2]) -> Style {
+        Style {
+            visible: self.visible && visible,
+            opacity: self.opacity * opacity,
+            offset: [self.offset[0] + offset[0], self.offset[1] + offset[1]],
+        }
+    }
+}
+
+/// A single image tileset. Collections of separate images are not supported
+pub struct Tileset {
+    pub first_gid: u32,
+    pub tile_count: u32,
+    pub columns: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub margin: u32,
+    pub spacing: u32,
+    /// Asset name of the image
+    pub image: String,
+    pub image_size: [u32; 2],
+}
+
+/// Tiles of a single layer sharing a tileset, as a mesh
+pub struct Batch {
+    pub tileset: usize,
+    /// Of the layer, multiplies the alpha of the tileset image
+    pub opacity: f32,
+    pub positions: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// What a map file says, before its external tilesets are read
+struct Parsed {
+    data: MapData,
+    layers: Vec<Vec<u32>>,
+    styles: Vec<Style>,
+    tilesets: Vec<TilesetSource>,
+}
+
+enum TilesetSource {
+    Inline(Tileset, Vec<(u32, Vec<Property>)>),
+    External(u32, String),
+}
+
+/// Read a `.tmx` or Tiled JSON map, along with its tilesets and their images
+pub async fn load(assets: &Assets, asset: &str) -> Result<Map, String> {
+    puffin::profile_function!();
+
+    let cache = assets.find(asset).await?;
+    let mut sources = vec![cache.path.clone()];
+    let bytes = cache.load_all().await.map_err(|e| e.to_string())?;
+
+    let parsed = if is_json(asset) {
+        json_map(&json(&bytes)?, asset)?
+    } else {
+        xml_map(&xml(&bytes)?, asset)?
+    };
+
+    let mut data = parsed.data;
+    let mut tilesets = vec![];
+    for source in parsed.tilesets {
+        let (tileset, properties) = match source {
+            TilesetSource::Inline(tileset, properties) => (tileset, properties),
+            TilesetSource::External(first_gid, name) => {
+                let cache = assets.find(&name).await?;
+                sources.push(cache.path.clone());
+                let bytes = cache.load_all().await.map_err(|e| e.to_string())?;
+
+                if is_json(&name) {
+                    json_tileset(&json(&bytes)?, first_gid, &name)?
+                } else {
+                    xml_tileset(&xml(&bytes)?, first_gid, &name)?
+                }
+            }
+        };
+
+        data.tile_properties.extend(
+            properties
+                .into_iter()
+                .map(|(id, properties)| (tileset.first_gid + id, properties)),
+        );
+        tilesets.push(tileset);
+    }
+
+    let mut images = vec![];
+    for tileset in &tilesets {
+        let image = match assets.find(&tileset.image).await {
+            Ok(cache) => {
+                sources.push(cache.path.clone());
+                match cache.load_all().await {
+                    Ok(bytes) => image::load_from_memory(&bytes)
+                        .map(|img| img.to_rgba8())
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        match image {
+            Ok(image) => images.push(Some(image)),
+            Err(e) => {
+                assets.fail(&tileset.image, e);
+                images.push(None);
+            }
+        }
+    }
+
+    debug!(
+        "Loaded {}x{} tile map {} with {} tilesets",
+        data.width,
+        data.height,
+        asset,
+        tilesets.len()
+    );
+
+    Ok(Map {
+        data,
+        path: cache.path.clone(),
+        sources,
+        tilesets,
+        images,
+        layers: parsed.layers,
+        styles: parsed.styles,
+    })
+}
+
+impl Map {
+    /// Quads of every tile, one batch per layer and tileset. One unit per
+    /// map tile, with the top left corner of the map at the origin. Hidden
+    /// layers are left out, the game still gets their tiles
+    pub fn batches(&self) -> Vec<Batch> {
+        puffin::profile_function!();
+
+        let tile_width = self.data.tile_width as f32;
+        let tile_height = self.data.tile_height as f32;
+
+        let mut batches = vec![];
+        let layers = self.data.layers.iter().zip(&self.layers).zip(&self.styles);
+        for (depth, ((layer, tiles), style)) in layers.enumerate() {
+            if layer.width == 0 || !style.visible || style.opacity <= 0.0 {
+                continue;
+            }
+
+            let z = -(depth as f32) * LAYER_DEPTH;
+            let offset = [style.offset[0] / tile_width, style.offset[1] / tile_height];
+            let mut layer_batches: BTreeMap<usize, Batch> = BTreeMap::new();
+
+            for (i, raw) in tiles.iter().enumerate() {
+                let gid = raw & GID_MASK;
+                let tileset = match self.tileset(gid) {
+                    Some(tileset) => tileset,
+                    None => continue,
+                };
+                let ts = &self.tilesets[tileset];
+
+                let (col, row) = (
+                    (i as u32 % layer.width) as f32 + offset[0],
+                    (i as u32 / layer.width) as f32 + offset[1],
+                );
+
+                // bigger tiles stick out of their cell upwards and to the right
+                let left = col;
+                let right = col + ts.tile_width as f32 / tile_width;
+                let bottom = -(row + 1.0);
+                let top = bottom + ts.tile_height as f32 / tile_height;
+
+                let batch = layer_batches.entry(tileset).or_insert_with(|| Batch {
+                    tileset,
+                    opacity: style.opacity.min(1.0),
+                    positions: vec![],
+                    uvs: vec![],
+                    indices: vec![],
+                });
+
+                let base = batch.positions.len() as u32;
+                batch.positions.extend_from_slice(&[
+                    [left, bottom, z],
+                    [right, bottom, z],
+                    [right, top, z],
+                    [left, top, z],
+                ]);
+                batch
+                    .uvs
+                    .extend_from_slice(&ts.uvs(gid - ts.first_gid, *raw));
+                batch.indices.extend(
+                    [2, 1, 0, 0, 3, 2, 2, 3, 0, 0, 1, 2]
+                        .iter()
+                        .map(|index| base + index),
+                );
+            }
+
+            batches.extend(layer_batches.into_iter().map(|(_, batch)| batch));
+        }
+
+        batches
+    }
+
+    /// Index of the tileset a global tile id belongs to
+    fn tileset(&self, gid: u32) -> Option<usize> {
+        if gid == 0 {
+            return None;
+        }
+
+        self.tilesets
+            .iter()
+            .rposition(|ts| ts.first_gid <= gid)
+            .filter(|&i| gid - self.tilesets[i].first_gid < self.tilesets[i].tile_count)
+    }
+}
+
+impl Tileset {
+    /// Texture coordinates of the corners of a tile, bottom left first and
+    /// going counter clockwise
+    fn uvs(&self, id: u32, raw: u32) -> [[f32; 2]; 4] {
+        let (col, row) = (id % self.columns.max(1), id / self.columns.max(1));
+        let (width, height) = (self.image_size[0] as f32, self.image_size[1] as f32);
+
+        let x = start(self.margin, col, self.tile_width, self.spacing);
+        let y = start(self.margin, row, self.tile_height, self.spacing);
+        let (u0, u1) = (x / width, (x + self.tile_width as f32) / width);
+        let (v0, v1) = (y / height, (y + self.tile_height as f32) / height);
+
+        let mut uvs = [[u0, v1], [u1, v1], [u1, v0], [u0, v0]];
+
+        // Tiled flips diagonally first, then horizontally and vertically
+        if raw & FLIPPED_DIAGONALLY != 0 {
+            uvs.swap(0, 2);
+        }
+        if raw & FLIPPED_HORIZONTALLY != 0 {
+            uvs.swap(0, 1);
+            uvs.swap(2, 3);
+        }
+        if raw & FLIPPED_VERTICALLY != 0 {
+            uvs.swap(0, 3);
+            uvs.swap(1, 2);
+        }
+
+        uvs
+    }
+}
+
+/// Pixel the tile at `index` starts at along one axis. Tilesets are read from
+/// files, so a broken one saturates instead of overflowing
+fn start(margin: u32, index: u32, size: u32, spacing: u32) -> f32 {
+    let step = size.saturating_add(spacing);
+    margin.saturating_add(index.saturating_mul(step)) as f32
+}
+
+fn is_json(asset: &str) -> bool {
+    asset.ends_with(".json") || asset.ends_with(".tmj") || asset.ends_with(".tsj")
+}
+
+/// Asset name of a file referenced from another asset
+fn relative(from: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+
+    for part in path.split(|c| c == '/' || c == '\\') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
+
+/// Refuse maps we can't draw
+fn check_map(orientation: Option<&str>, infinite: bool) -> Result<(), String> {
+    if orientation.map_or(false, |o| o != "orthogonal") {
+        return Err("only orthogonal maps are supported".into());
+    }
+    if infinite {
+        return Err("infinite maps are not supported".into());
+    }
+
+    Ok(())
+}
+
+/// Flip flags stay in the tiles drawn, the game gets plain tile ids
+fn layer(
+    name: String,
+    width: u32,
+    height: u32,
+    tiles: Vec<u32>,
+    properties: Vec<Property>,
+) -> (TileLayer, Vec<u32>) {
+    let plain = tiles.iter().map(|tile| tile & GID_MASK).collect();
+    let layer = TileLayer {
+        name,
+        width,
+        height,
+        tiles: plain,
+        properties,
+    };
+
+    (layer, tiles)
+}
+
+fn decode_tiles(data: &str, encoding: &str, compression: &str) -> Result<Vec<u32>, String> {
+    match encoding {
+        "csv" => data
+            .split(',')
+            .map(str::trim)
+            .filter(|tile| !tile.is_empty())
+            .map(|tile| tile.parse().map_err(|_| format!("bad tile {:?}", tile)))
+            .collect(),
+        "base64" => {
+            let bytes = base64::decode(data.trim()).map_err(|e| e.to_string())?;
+            let bytes = match compression {
+                "" => bytes,
+                "zlib" => miniz_oxide::inflate::decompress_to_vec_zlib(&bytes)
+                    .map_err(|e| format!("corrupt layer data: {:?}", e))?,
+                // no optional gzip header fields, which Tiled never writes
+                "gzip" if bytes.len() > 18 && bytes[3] == 0 => {
+                    miniz_oxide::inflate::decompress_to_vec(&bytes[10..bytes.len() - 8])
+                        .map_err(|e| format!("corrupt layer data: {:?}", e))?
+                }
+                other => return Err(format!("unsupported layer compression {:?}", other)),
+            };
+
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+        other => Err(format!("unsupported layer encoding {:?}", other)),
+    }
+}
+
+fn property(name: String, kind: &str, value: &str) -> Property {
+    let value = match kind {
+        "bool" => PropertyValue::Bool(value == "true"),
+        "int" | "object" => PropertyValue::Int(value.parse().unwrap_or_default()),
+        "float" => PropertyValue::Float(value.parse().unwrap_or_default()),
+        _ => PropertyValue::String(value.into()),
+    };
+
+    Property { name, value }
+}
+
+/// Object in tiles with y going up, from Tiled's pixels going down. `offset`
+/// is that of the layer the object is on
+fn object(
+    data: &MapData,
+    id: u32,
+    name: String,
+    kind: String,
+    pixels: [f32; 4],
+    offset: [f32; 2],
+    tile: Option<u32>,
+    properties: Vec<Property>,
+) -> MapObject {
+    let [mut x, mut y, width, height] = pixels;
+    x += offset[0];
+    y += offset[1];
+    // tile objects are placed by their bottom left corner
+    if tile.is_some() {
+        y -= height;
+    }
+
+    let (tile_width, tile_height) = (data.tile_width as f32, data.tile_height as f32);
+    MapObject {
+        id,
+        name,
+        kind,
+        position: [x / tile_width, -y / tile_height],
+        size: [width / tile_width, height / tile_height],
+        tile: tile.map(|gid| gid & GID_MASK),
+        properties,
+    }
+}
+
+fn empty_map(
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+) -> Result<MapData, String> {
+    if tile_width == 0 || tile_height == 0 {
+        return Err("map has no tile size".into());
+    }
+
+    Ok(MapData {
+        width,
+        height,
+        tile_width,
+        tile_height,
+        properties: vec![],
+        layers: vec![],
+        object_layers: vec![],
+        tile_properties: vec![],
+    })
+}
+
+// TMX
+
+/// Just enough of an XML document to read Tiled files
+#[derive(Default)]
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(|s| s.as_str())
+    }
+
+    /// Numeric attribute, zero when missing
+    fn num<T: std::str::FromStr + Default>(&self, name: &str) -> T {
+        self.attr(name)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    fn string(&self, name: &str) -> String {
+        self.attr(name).unwrap_or_default().into()
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn properties(&self) -> Vec<Property> {
+        let properties = match self.child("properties") {
+            Some(properties) => properties,
+            None => return vec![],
+        };
+
+        properties
+            .children
+            .iter()
+            .map(|p| {
+                // multiline strings are kept as text instead
+                let value = p.attr("value").unwrap_or(&p.text);
+                property(p.string("name"), p.attr("type").unwrap_or("string"), value)
+            })
+            .collect()
+    }
+}
+
+fn xml(bytes: &[u8]) -> Result<Element, String> {
+    let mut stack = vec![Element::default()];
+
+    for event in EventReader::new(bytes) {
+        match event.map_err(|e| e.to_string())? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => stack.push(Element {
+                name: name.local_name,
+                attributes: attributes
+                    .into_iter()
+                    .map(|a| (a.name.local_name, a.value))
+                    .collect(),
+                ..Default::default()
+            }),
+            XmlEvent::EndElement { .. } => {
+                let element = stack.pop().unwrap();
+                stack.last_mut().unwrap().children.push(element);
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                stack.last_mut().unwrap().text.push_str(&text)
+            }
+            _ => (),
+        }
+    }
+
+    let mut document = stack.pop().unwrap();
+    document
+        .children
+        .pop()
+        .ok_or_else(|| "empty document".into())
+}
+
+fn xml_map(root: &Element, asset: &str) -> Result<Parsed, String> {
+    if root.name != "map" {
+        return Err("not a Tiled map".into());
+    }
+    check_map(root.attr("orientation"), root.num::<u32>("infinite") != 0)?;
+
+    let mut data = empty_map(
+        root.num("width"),
+        root.num("height"),
+        root.num("tilewidth"),
+        root.num("tileheight"),
+    )?;
+    data.properties = root.properties();
+
+    let mut parsed = Parsed {
+        data,
+        layers: vec![],
+        styles: vec![],
+        tilesets: vec![],
+    };
+
+    for tileset in root.children.iter().filter(|c| c.name == "tileset") {
+        let first_gid = tileset.num("firstgid");
+        parsed.tilesets.push(match tileset.attr("source") {
+            Some(source) => TilesetSource::External(first_gid, relative(asset, source)),
+            None => {
+                let (tileset, properties) = xml_tileset(tileset, first_gid, asset)?;
+                TilesetSource::Inline(tileset, properties)
+            }
+        });
+    }
+
+    xml_layers(root, Style::ROOT, &mut parsed)?;
+    Ok(parsed)
+}
+
+/// Layers in drawing order, groups flattened
+fn xml_layers(parent: &Element, style: Style, parsed: &mut Parsed) -> Result<(), String> {
+    for element in &parent.children {
+        let float = |name, default| {
+            element
+                .attr(name)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        let style = style.nest(
+            element.attr("visible") != Some("0"),
+            float("opacity", 1.0),
+            [float("offsetx", 0.0), float("offsety", 0.0)],
+        );
+
+        match element.name.as_str() {
+            "layer" => {
+                let name = element.string("name");
+                let data = element.child("data").ok_or("tile layer without data")?;
+                let tiles = match data.attr("encoding") {
+                    Some(encoding) => decode_tiles(
+                        &data.text,
+                        encoding,
+                        data.attr("compression").unwrap_or_default(),
+                    )?,
+                    None => data.children.iter().map(|tile| tile.num("gid")).collect(),
+                };
+
+                let (layer, tiles) = layer(
+                    name,
+                    element.num("width"),
+                    element.num("height"),
+                    tiles,
+                    element.properties(),
+                );
+                parsed.data.layers.push(layer);
+                parsed.layers.push(tiles);
+                parsed.styles.push(style);
+            }
+            "objectgroup" => {
+                let objects = element
+                    .children
+                    .iter()
+                    .filter(|c| c.name == "object")
+                    .map(|o| {
+                        let kind = o.attr("class").or_else(|| o.attr("type"));
+                        object(
+                            &parsed.data,
+                            o.num("id"),
+                            o.string("name"),
+                            kind.unwrap_or_default().into(),
+                            [o.num("x"), o.num("y"), o.num("width"), o.num("height")],
+                            style.offset,
+                            o.attr("gid").and_then(|gid| gid.parse().ok()),
+                            o.properties(),
+                        )
+                    })
+                    .collect();
+
+                parsed.data.object_layers.push(ObjectLayer {
+                    name: element.string("name"),
+                    objects,
+                    properties: element.properties(),
+                });
+            }
+            "group" => xml_layers(element, style, parsed)?,
+            "imagelayer" => warn!("Skipping image layer {:?}", element.string("name")),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Image paths are relative to the file the tileset is in
+fn xml_tileset(
+    element: &Element,
+    first_gid: u32,
+    asset: &str,
+) -> Result<(Tileset, Vec<(u32, Vec<Property>)>), String> {
+    let image = element
+        .child("image")
+        .ok_or("tilesets of separate images are not supported")?;
+
+    let tileset = Tileset {
+        first_gid,
+        tile_count: element.num("tilecount"),
+        columns: element.num("columns"),
+        tile_width: element.num("tilewidth"),
+        tile_height: element.num("tileheight"),
+        margin: element.num("margin"),
+        spacing: element.num("spacing"),
+        image: relative(asset, image.attr("source").unwrap_or_default()),
+        image_size: [image.num("width"), image.num("height")],
+    };
+
+    let properties = element
+        .children
+        .iter()
+        .filter(|c| c.name == "tile")
+        .map(|tile| (tile.num("id"), tile.properties()))
+        .filter(|(_, properties)| !properties.is_empty())
+        .collect();
+
+    Ok((tileset, properties))
+}
+
+// JSON
+
+fn json(bytes: &[u8]) -> Result<Value, String> {
+    serde_json::from_slice(bytes).map_err(|e| e.to_string())
+}
+
+fn num<T: std::convert::TryFrom<u64> + Default>(value: &Value, key: &str) -> T {
+    value[key]
+        .as_u64()
+        .and_then(|n| T::try_from(n).ok())
+        .unwrap_or_default()
+}
+
+fn float(value: &Value, key: &str) -> f32 {
+    value[key].as_f64().unwrap_or_default() as f32
+}
+
+fn string(value: &Value, key: &str) -> String {
+    value[key].as_str().unwrap_or_default().into()
+}
+
+fn json_properties(value: &Value) -> Vec<Property> {
+    let properties = match value["properties"].as_array() {
+        Some(properties) => properties,
+        None => return vec![],
+    };
+
+    properties
+        .iter()
+        .map(|p| {
+            let value = match &p["value"] {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            property(
+                string(p, "name"),
+                p["type"].as_str().unwrap_or("string"),
+                &value,
+            )
+        })
+        .collect()
+}
+
+fn json_map(root: &Value, asset: &str) -> Result<Parsed, String> {
+    if root["type"] != "map" {
+        return Err("not a Tiled map".into());
+    }
+    check_map(root["orientation"].as_str(), root["infinite"] == true)?;
+
+    let mut data = empty_map(
+        num(root, "width"),
+        num(root, "height"),
+        num(root, "tilewidth"),
+        num(root, "tileheight"),
+    )?;
+    data.properties = json_properties(root);
+
+    let mut parsed = Parsed {
+        data,
+        layers: vec![],
+        styles: vec![],
+        tilesets: vec![],
+    };
+
+    for tileset in root["tilesets"].as_array().into_iter().flatten() {
+        let first_gid = num(tileset, "firstgid");
+        parsed.tilesets.push(match tileset["source"].as_str() {
+            Some(source) => TilesetSource::External(first_gid, relative(asset, source)),
+            None => {
+                let (tileset, properties) = json_tileset(tileset, first_gid, asset)?;
+                TilesetSource::Inline(tileset, properties)
+            }
+        });
+    }
+
+    json_layers(root, Style::ROOT, &mut parsed)?;
+    Ok(parsed)
+}
+
+fn json_layers(parent: &Value, style: Style, parsed: &mut Parsed) -> Result<(), String> {
+    for layer_value in parent["layers"].as_array().into_iter().flatten() {
+        let style = style.nest(
+            layer_value["visible"] != false,
+            layer_value["opacity"].as_f64().unwrap_or(1.0) as f32,
+            [float(layer_value, "offsetx"), float(layer_value, "offsety")],
+        );
+
+        match layer_value["type"].as_str().unwrap_or_default() {
+            "tilelayer" => {
+                let tiles = match &layer_value["data"] {
+                    Value::Array(tiles) => tiles
+                        .iter()
+                        .map(|tile| tile.as_u64().unwrap_or_default() as u32)
+                        .collect(),
+                    Value::String(data) => decode_tiles(
+                        data,
+                        layer_value["encoding"].as_str().unwrap_or_default(),
+                        layer_value["compression"].as_str().unwrap_or_default(),
+                    )?,
+                    _ => return Err("tile layer without data".into()),
+                };
+
+                let (layer, tiles) = layer(
+                    string(layer_value, "name"),
+                    num(layer_value, "width"),
+                    num(layer_value, "height"),
+                    tiles,
+                    json_properties(layer_value),
+                );
+                parsed.data.layers.push(layer);
+                parsed.layers.push(tiles);
+                parsed.styles.push(style);
+            }
+            "objectgroup" => {
+                let objects = layer_value["objects"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|o| {
+                        let kind = o["class"].as_str().or_else(|| o["type"].as_str());
+                        object(
+                            &parsed.data,
+                            num(o, "id"),
+                            string(o, "name"),
+                            kind.unwrap_or_default().into(),
+                            [
+                                float(o, "x"),
+                                float(o, "y"),
+                                float(o, "width"),
+                                float(o, "height"),
+                            ],
+                            style.offset,
+                            o["gid"].as_u64().map(|gid| gid as u32),
+                            json_properties(o),
+                        )
+                    })
+                    .collect();
+
+                parsed.data.object_layers.push(ObjectLayer {
+                    name: string(layer_value, "name"),
+                    objects,
+                    properties: json_properties(layer_value),
+                });
+            }
+            "group" => json_layers(layer_value, style, parsed)?,
+            "imagelayer" => warn!("Skipping image layer {:?}", string(layer_value, "name")),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn json_tileset(
+    value: &Value,
+    first_gid: u32,
+    asset: &str,
+) -> Result<(Tileset, Vec<(u32, Vec<Property>)>), String> {
+    let image = value["image"]
+        .as_str()
+        .ok_or("tilesets of separate images are not supported")?;
+
+    let tileset = Tileset {
+        first_gid,
+        tile_count: num(value, "tilecount"),
+        columns: num(value, "columns"),
+        tile_width: num(value, "tilewidth"),
+        tile_height: num(value, "tileheight"),
+        margin: num(value, "margin"),
+        spacing: num(value, "spacing"),
+        image: relative(asset, image),
+        image_size: [num(value, "imagewidth"), num(value, "imageheight")],
+    };
+
+    let properties = value["tiles"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|tile| (num(tile, "id"), json_properties(tile)))
+        .filter(|(_, properties)| !properties.is_empty())
+        .collect();
+
+    Ok((tileset, properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tiles of the test map, flipped horizontally, vertically and
+    /// diagonally after the first
+    const RAW: [u32; 4] = [
+        1,
+        2 | FLIPPED_HORIZONTALLY,
+        3 | FLIPPED_VERTICALLY,
+        4 | FLIPPED_DIAGONALLY,
+    ];
+
+    fn bytes() -> Vec<u8> {
+        RAW.iter()
+            .flat_map(|tile| tile.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn zlib() -> String {
+        base64::encode(miniz_oxide::deflate::compress_to_vec_zlib(&bytes(), 6))
+    }
+
+    /// As Tiled writes it, a bare header, deflated data, CRC-32 and length
+    fn gzip() -> String {
+        let data = bytes();
+        let mut crc = !0u32;
+        for byte in &data {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+            }
+        }
+
+        let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        gzip.extend(miniz_oxide::deflate::compress_to_vec(&data, 6));
+        gzip.extend_from_slice(&(!crc).to_le_bytes());
+        gzip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        base64::encode(gzip)
+    }
+
+    fn tmx(data: &str) -> Element {
+        let map = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="2" height="2" tilewidth="16" tileheight="16" infinite="0">
+ <properties>
+  <property name="gravity" type="float" value="9.5"/>
+ </properties>
+ <tileset firstgid="1" name="tiles" tilewidth="16" tileheight="16" spacing="2" margin="1" tilecount="4" columns="2">
+  <image source="tiles.png" width="36" height="36"/>
+  <tile id="1">
+   <properties>
+    <property name="kind" value="spike"/>
+   </properties>
+  </tile>
+ </tileset>
+ <layer id="1" name="ground" width="2" height="2">
+  <properties>
+   <property name="solid" type="bool" value="true"/>
+  </properties>
+  {}
+ </layer>
+ <objectgroup id="2" name="things">
+  <object id="1" name="spawn" type="player" x="16" y="8" width="16" height="16">
+   <properties>
+    <property name="hp" type="int" value="3"/>
+   </properties>
+  </object>
+  <object id="2" gid="2147483649" x="0" y="32" width="16" height="16"/>
+ </objectgroup>
+</map>"#,
+            data
+        );
+
+        xml(map.as_bytes()).unwrap()
+    }
+
+    fn tmj(data: &str) -> Value {
+        let map = format!(
+            r#"{{
+ "type": "map", "orientation": "orthogonal", "infinite": false,
+ "width": 2, "height": 2, "tilewidth": 16, "tileheight": 16,
+ "properties": [{{ "name": "gravity", "type": "float", "value": 9.5 }}],
+ "tilesets": [{{
+  "firstgid": 1, "name": "tiles", "tilewidth": 16, "tileheight": 16,
+  "spacing": 2, "margin": 1, "tilecount": 4, "columns": 2,
+  "image": "tiles.png", "imagewidth": 36, "imageheight": 36,
+  "tiles": [{{ "id": 1, "properties": [{{ "name": "kind", "type": "string", "value": "spike" }}] }}]
+ }}],
+ "layers": [
+  {{
+   "type": "tilelayer", "name": "ground", "width": 2, "height": 2, {},
+   "properties": [{{ "name": "solid", "type": "bool", "value": true }}]
+  }},
+  {{
+   "type": "objectgroup", "name": "things",
+   "objects": [
+    {{
+     "id": 1, "name": "spawn", "type": "player", "x": 16, "y": 8, "width": 16, "height": 16,
+     "properties": [{{ "name": "hp", "type": "int", "value": 3 }}]
+    }},
+    {{ "id": 2, "name": "", "gid": 2147483649, "x": 0, "y": 32, "width": 16, "height": 16 }}
+   ]
+  }}
+ ]
+}}"#,
+            data
+        );
+
+        json(map.as_bytes()).unwrap()
+    }
+
+    fn check(parsed: Parsed) {
+        let data = &parsed.data;
+        assert_eq!((data.width, data.height), (2, 2));
+        assert_eq!(
+            data.properties,
+            vec![property("gravity".into(), "float", "9.5")]
+        );
+
+        let layer = &data.layers[0];
+        assert_eq!(layer.name, "ground");
+        assert_eq!(layer.tiles, vec![1, 2, 3, 4]);
+        assert_eq!(
+            layer.properties,
+            vec![property("solid".into(), "bool", "true")]
+        );
+        assert_eq!(parsed.layers, vec![RAW.to_vec()]);
+
+        let things = &data.object_layers[0];
+        assert_eq!(things.name, "things");
+        let spawn = &things.objects[0];
+        assert_eq!(
+            (spawn.id, &*spawn.name, &*spawn.kind),
+            (1, "spawn", "player")
+        );
+        assert_eq!(spawn.position, [1.0, -0.5]);
+        assert_eq!(spawn.size, [1.0, 1.0]);
+        assert_eq!(spawn.tile, None);
+        assert_eq!(spawn.properties, vec![property("hp".into(), "int", "3")]);
+        // tile objects sit on their bottom edge
+        let tile = &things.objects[1];
+        assert_eq!(tile.tile, Some(1));
+        assert_eq!(tile.position, [0.0, -1.0]);
+
+        match &parsed.tilesets[..] {
+            [TilesetSource::Inline(tileset, properties)] => {
+                assert_eq!(tileset.image, "maps/tiles.png");
+                assert_eq!(tileset.image_size, [36, 36]);
+                assert_eq!(
+                    (tileset.columns, tileset.margin, tileset.spacing),
+                    (2, 1, 2)
+                );
+                assert_eq!(
+                    properties,
+                    &vec![(1, vec![property("kind".into(), "string", "spike")])]
+                );
+            }
+            _ => panic!("expected a single inline tileset"),
+        }
+    }
+
+    #[test]
+    fn reads_tmx_maps() {
+        let csv = r#"<data encoding="csv">1,2147483650,
+1073741827,536870916</data>"#;
+        check(xml_map(&tmx(csv), "maps/level.tmx").unwrap());
+
+        let zlib = format!(
+            r#"<data encoding="base64" compression="zlib">{}</data>"#,
+            zlib()
+        );
+        check(xml_map(&tmx(&zlib), "maps/level.tmx").unwrap());
+
+        let gzip = format!(
+            r#"<data encoding="base64" compression="gzip">{}</data>"#,
+            gzip()
+        );
+        check(xml_map(&tmx(&gzip), "maps/level.tmx").unwrap());
+    }
+
+    #[test]
+    fn reads_json_maps() {
+        let csv = r#""data": [1, 2147483650, 1073741827, 536870916]"#;
+        check(json_map(&tmj(csv), "maps/level.tmj").unwrap());
+
+        let zlib = format!(
+            r#""encoding": "base64", "compression": "zlib", "data": "{}""#,
+            zlib()
+        );
+        check(json_map(&tmj(&zlib), "maps/level.tmj").unwrap());
+
+        let gzip = format!(
+            r#""encoding": "base64", "compression": "gzip", "data": "{}""#,
+            gzip()
+        );
+        check(json_map(&tmj(&gzip), "maps/level.tmj").unwrap());
+    }
+
+    #[test]
+    fn refuses_broken_layer_data() {
+        assert!(decode_tiles("1,x", "csv", "").is_err());
+        assert!(decode_tiles("AAAA", "base64", "zstd").is_err());
+        assert!(decode_tiles("AAAAAAAAAAAAAAAAAAAAAAAAAAAA", "base64", "gzip").is_err());
+        assert!(decode_tiles("1", "hex", "").is_err());
+    }
+
+    fn tileset() -> Tileset {
+        Tileset {
+            first_gid: 1,
+            tile_count: 4,
+            columns: 2,
+            tile_width: 16,
+            tile_height: 16,
+            margin: 1,
+            spacing: 2,
+            image: "tiles.png".into(),
+            image_size: [36, 36],
+        }
+    }
+
+    #[test]
+    fn flips_tiles() {
+        let tileset = tileset();
+        // the last tile, past the margin and a tile and its spacing
+        let (u0, u1) = (19.0 / 36.0, 35.0 / 36.0);
+        let (v0, v1) = (19.0 / 36.0, 35.0 / 36.0);
+
+        assert_eq!(tileset.uvs(3, 4), [[u0, v1], [u1, v1], [u1, v0], [u0, v0]]);
+        assert_eq!(
+            tileset.uvs(3, 4 | FLIPPED_HORIZONTALLY),
+            [[u1, v1], [u0, v1], [u0, v0], [u1, v0]]
+        );
+        assert_eq!(
+            tileset.uvs(3, 4 | FLIPPED_VERTICALLY),
+            [[u0, v0], [u1, v0], [u1, v1], [u0, v1]]
+        );
+        // the top left and bottom right corners stay put
+        assert_eq!(
+            tileset.uvs(3, 4 | FLIPPED_DIAGONALLY),
+            [[u1, v0], [u1, v1], [u0, v1], [u0, v0]]
+        );
+        // turned a quarter clockwise
+        assert_eq!(
+            tileset.uvs(3, 4 | FLIPPED_DIAGONALLY | FLIPPED_HORIZONTALLY),
+            [[u1, v1], [u1, v0], [u0, v0], [u0, v1]]
+        );
+    }
+
+    #[test]
+    fn survives_huge_tilesets() {
+        let tileset = Tileset {
+            margin: u32::MAX,
+            spacing: u32::MAX,
+            columns: 1,
+            ..tileset()
+        };
+
+        let uvs = tileset.uvs(u32::MAX, u32::MAX);
+        assert!(uvs.iter().flatten().all(|uv| uv.is_finite()));
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use vg_native::{runtime::wasm::Wasm, Headless, Options};
+use vg_types::{Key, Response};
+
+/// The example game in `test/`
+fn game_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../test")
+}
+
+/// Build the example game for the wasm runtime, like `cargo vg build` does
+fn build_game() -> Vec<u8> {
+    let target = Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/headless-test");
+    let status = Command::new(env!("CARGO"))
+        .arg("build")
+        .arg("--manifest-path")
+        .arg(game_dir().join("Cargo.toml"))
+        .arg("--target")
+        .arg("wasm32-wasi")
+        .arg("--target-dir")
+        .arg(&target)
+        .status()
+        .expect("Failed to run cargo");
+    assert!(status.success(), "Failed to build the example game");
+
+    std::fs::read(target.join("wasm32-wasi/debug/rust-test.wasm")).unwrap()
+}
+
+#[test]
+fn runs_example_game() {
+    let opts = Options {
+        asset_paths: vec![game_dir().join("assets")],
+        ..Options::default()
+    };
+    let mut game = Headless::<Wasm>::load_with(opts, &build_game())
+        .unwrap()
+        .render(64, 48);
+
+    game.schedule(10, Response::Down(Key::D));
+    game.schedule(20, Response::Down(Key::Space));
+    game.schedule(21, Response::Up(Key::Space));
+    game.run(60).unwrap();
+
+    assert_eq!(game.ticks(), 60);
+    assert!(!game.exited());
+
+    // one ferris follows the keys, the other stays put. The first tick runs
+    // the game up to its first frame as well
+    assert!(game.draws(0).count() >= 2);
+    for tick in 1..60 {
+        let draws: Vec<_> = game.draws(tick).collect();
+        assert_eq!(draws.len(), 2, "tick {}", tick);
+        assert!(draws.iter().all(|draw| draw.asset == "ferris.png"));
+        assert_eq!(draws[1].trans.position[..2], [1.0, 1.0]);
+    }
+
+    let x = |tick| game.draws(tick).next().unwrap().trans.position[0];
+    assert_eq!(x(5), 0.0);
+    assert!(x(30) > x(15));
+
+    // space was pressed once
+    let plays: Vec<_> = (0..60)
+        .flat_map(|tick| game.plays(tick).map(move |play| (tick, play)))
+        .collect();
+    assert_eq!(plays.len(), 1);
+    assert_eq!(plays[0].1.asset, "cat.ogg");
+    assert!((20..=21).contains(&plays[0].0));
+
+    // ferris is red and green, nothing else is drawn
+    let image = game.image().unwrap();
+    assert_eq!(image.dimensions(), (64, 48));
+    assert!(image.pixels().any(|pixel| pixel[0] > 200 && pixel[1] < 50));
+}
@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+
+use vg_native::{build_pack, compare, Assets, Software};
+use vg_types::{DrawCall, Filter, Sampling, Transform, Wrap};
+
+/// Assets of the example game in `test/`
+fn assets_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../test/assets")
+}
+
+fn golden(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+}
+
+fn ferris(position: [f32; 3], scale: f32, rotation: [f32; 4], sampling: Sampling) -> DrawCall {
+    DrawCall {
+        asset: "ferris.png".into(),
+        trans: Transform {
+            position,
+            scale: [scale, scale, 1.0],
+            rotation,
+        },
+        id: None,
+        sampling,
+    }
+}
+
+/// A plain sprite, one turned 45 degrees with sharp pixels and a smoothed
+/// one with clamped edges, overlapping the others
+fn draws() -> Vec<DrawCall> {
+    let turned = [0.0, 0.0, 0.382_683_43, 0.923_879_5];
+    vec![
+        ferris(
+            [-3.0, 1.5, 0.0],
+            3.0,
+            Transform::IDENTITY.rotation,
+            Sampling::default(),
+        ),
+        ferris(
+            [0.0, 0.0, 0.0],
+            4.0,
+            turned,
+            Sampling {
+                filter: Some(Filter::Nearest),
+                ..Sampling::default()
+            },
+        ),
+        ferris(
+            [2.0, -1.5, 0.0],
+            3.0,
+            Transform::IDENTITY.rotation,
+            Sampling {
+                filter: Some(Filter::Linear),
+                wrap: Wrap::Clamp,
+                ..Sampling::default()
+            },
+        ),
+    ]
+}
+
+fn render(assets: &Assets) -> image::RgbaImage {
+    let mut renderer = Software::new(96, 64);
+    let tokio = tokio::runtime::Runtime::new().unwrap();
+    tokio.block_on(renderer.render(assets, &draws(), &[]))
+}
+
+#[test]
+fn renders_sprites() {
+    let assets = Assets::new(&[assets_dir()], &[]);
+
+    compare(&render(&assets), &golden("sprites.png"), 2).unwrap();
+}
+
+#[test]
+fn renders_sprites_from_packs() {
+    let pack = std::env::temp_dir().join(format!("vg-software-{}.vgpak", std::process::id()));
+    build_pack(&assets_dir(), &pack, true).unwrap();
+    let assets = Assets::new(&[pack.clone()], &[]);
+
+    let frame = render(&assets);
+    std::fs::remove_file(&pack).unwrap();
+    compare(&frame, &golden("sprites.png"), 2).unwrap();
+}
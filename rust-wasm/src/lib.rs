@@ -179,6 +179,21 @@ pub fn get_export(inst: &ModuleInst, name: &str) -> Result<ExternVal, Error> {
     Err(Error::ExportNotFound)
 }
 
+/// List the memories of a module instance, including imported ones
+pub fn module_mems(inst: &ModuleInst) -> &[MemAddr] {
+    &inst.mem_addrs
+}
+
+/// List the tables of a module instance, including imported ones
+pub fn module_tables(inst: &ModuleInst) -> &[TableAddr] {
+    &inst.table_addrs
+}
+
+/// List the globals of a module instance, including imported ones
+pub fn module_globals(inst: &ModuleInst) -> &[GlobalAddr] {
+    &inst.global_addrs
+}
+
 /// Allocate a host function.
 ///
 /// The function is expected to produce a result matching to its return type.
@@ -381,6 +396,26 @@ pub fn write_mem<T>(
     }
 }
 
+/// Get the whole content of a memory
+pub fn mem_data<T>(store: &Store<T>, memaddr: MemAddr) -> &[u8] {
+    assert!(store.mems.contains(memaddr));
+    &store.mems[memaddr].data
+}
+
+/// Replace the whole content of a memory, resizing it to the length of the data
+pub fn write_mem_data<T>(store: &mut Store<T>, memaddr: MemAddr, data: &[u8]) -> Option<Error> {
+    assert!(store.mems.contains(memaddr));
+    let mi = &mut store.mems[memaddr];
+    let pages = data.len() / PAGE_SIZE;
+    if data.len() % PAGE_SIZE != 0 || mi.max.map_or(false, |max| pages > max as usize) {
+        Some(Error::InvalidMemoryWrite)
+    } else {
+        mi.data.clear();
+        mi.data.extend_from_slice(data);
+        None
+    }
+}
+
 /// Get the size of a memory
 pub fn size_mem<T>(store: &Store<T>, memaddr: MemAddr) -> usize {
     assert!(store.mems.contains(memaddr));
@@ -187,6 +187,10 @@ impl FuncAddr {
     pub fn new(addr: Addr) -> FuncAddr {
         FuncAddr { 0: addr }
     }
+
+    pub fn addr(&self) -> Addr {
+        self.0
+    }
 }
 
 // impl_inst_store!(FuncInstStore, FuncInst, FuncAddr);
@@ -0,0 +1,101 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{call_host, ensure};
+
+/// An asset the engine could not load
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub asset: String,
+    pub reason: String,
+}
+
+/// Assets reported as failed during the last frame, each is only reported
+/// once. Missing textures are drawn as a placeholder and missing sounds are
+/// skipped either way, this is only for games that want to do more
+pub fn failures() -> &'static [Failure] {
+    &ensure().asset_failures
+}
+
+/// Assets being loaded ahead of their first use, see [`preload`]
+pub struct Preload {
+    assets: Vec<String>,
+    frame: Option<Pin<Box<dyn Future<Output = ()>>>>,
+}
+
+/// Decode and upload assets before they are first drawn or played, so they
+/// don't hitch the frame they first appear in. Awaiting waits out the frames
+/// until everything is ready, draw a loading screen in the meantime with
+/// [`Preload::progress`] instead:
+///
+/// ```ignore
+/// let loading = vg::assets::preload(&["hero.png", "theme.ogg"]);
+/// while !loading.done() {
+///     draw_bar(loading.progress());
+///     vg::frame().await;
+/// }
+/// ```
+///
+/// Assets that fail to load count as ready, and show up in [`failures`]
+pub fn preload(assets: &[&str]) -> Preload {
+    let assets: Vec<String> = assets.iter().map(|asset| asset.to_string()).collect();
+
+    let missing: Vec<String> = assets
+        .iter()
+        .filter(|asset| !ensure().preloaded.contains(*asset))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        call_host(vg_types::Call::Preload(missing));
+    }
+
+    Preload {
+        assets,
+        frame: None,
+    }
+}
+
+impl Preload {
+    /// How much is ready, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.assets.is_empty() {
+            return 1.0;
+        }
+
+        let preloaded = &ensure().preloaded;
+        let ready = self
+            .assets
+            .iter()
+            .filter(|asset| preloaded.contains(*asset))
+            .count();
+
+        ready as f32 / self.assets.len() as f32
+    }
+
+    pub fn done(&self) -> bool {
+        let preloaded = &ensure().preloaded;
+        self.assets.iter().all(|asset| preloaded.contains(asset))
+    }
+}
+
+impl Future for Preload {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if self.done() {
+                return Poll::Ready(());
+            }
+
+            // the engine only answers between frames
+            let frame = self.frame.get_or_insert_with(|| Box::pin(crate::frame()));
+            match frame.as_mut().poll(cx) {
+                Poll::Ready(()) => self.frame = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
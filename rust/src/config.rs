@@ -0,0 +1,71 @@
+use vg_types::ConfigChange;
+pub use vg_types::Presentation;
+
+/// Changes to window and timing settings, sent to the engine when dropped.
+/// Settings that are not set keep whatever they were set to before
+pub struct Configure {
+    config: ConfigChange,
+}
+
+pub fn config() -> Configure {
+    Configure {
+        config: ConfigChange::default(),
+    }
+}
+
+impl Configure {
+    pub fn title(mut self, title: impl AsRef<str>) -> Configure {
+        self.config.title = Some(title.as_ref().into());
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Configure {
+        self.config.size = Some([width, height]);
+        self
+    }
+
+    pub fn resizable(mut self, resizable: bool) -> Configure {
+        self.config.resizable = Some(resizable);
+        self
+    }
+
+    pub fn fullscreen(mut self, fullscreen: bool) -> Configure {
+        self.config.fullscreen = Some(fullscreen);
+        self
+    }
+
+    /// Fixed simulation ticks per second
+    pub fn tick_rate(mut self, rate: f64) -> Configure {
+        self.config.tick_rate = Some(rate);
+        self
+    }
+
+    /// Most ticks run in a single frame when the game falls behind
+    pub fn max_catch_up(mut self, ticks: u32) -> Configure {
+        self.config.max_catch_up = Some(ticks);
+        self
+    }
+
+    pub fn vsync(mut self, vsync: bool) -> Configure {
+        self.config.vsync = Some(vsync);
+        self
+    }
+
+    /// Limit presented frames per second, `None` lifts the limit
+    pub fn frame_cap(mut self, fps: impl Into<Option<f64>>) -> Configure {
+        self.config.frame_cap = Some(fps.into());
+        self
+    }
+
+    /// How frames between fixed ticks are produced
+    pub fn presentation(mut self, presentation: Presentation) -> Configure {
+        self.config.presentation = Some(presentation);
+        self
+    }
+}
+
+impl Drop for Configure {
+    fn drop(&mut self) {
+        super::call_host(vg_types::Call::Configure(self.config.clone()))
+    }
+}
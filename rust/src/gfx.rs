@@ -1,16 +1,26 @@
-use vg_types::Transform;
+pub use vg_types::{Filter, Wrap};
+use vg_types::{Sampling, TilemapCall, Transform};
 
-use crate::{Position, Rotation};
+use crate::{
+    scene::{combine, Parent},
+    Position, Rotation,
+};
 
 pub struct Draw {
     asset: String,
     transform: Transform,
+    parent: Option<Transform>,
+    id: Option<u64>,
+    sampling: Sampling,
 }
 
 pub fn draw(asset: impl AsRef<str>) -> Draw {
     Draw {
         asset: asset.as_ref().into(),
         transform: Transform::IDENTITY,
+        parent: None,
+        id: None,
+        sampling: Sampling::default(),
     }
 }
 
@@ -29,13 +39,107 @@ impl Draw {
         self.transform.scale = scale.to_vec3();
         self
     }
+
+    /// Place the sprite relative to a scene node, position, rotation and
+    /// scale become local to it
+    pub fn parent(mut self, parent: impl Parent) -> Draw {
+        self.parent = Some(parent.world());
+        self
+    }
+
+    /// Stable id of this sprite, lets interpolated presentation follow it
+    /// from one tick to the next
+    pub fn id(mut self, id: u64) -> Draw {
+        self.id = Some(id);
+        self
+    }
+
+    /// Nearest for sharp pixel art, linear for smooth art. Picked from the
+    /// image size by default
+    pub fn filter(mut self, filter: Filter) -> Draw {
+        self.sampling.filter = Some(filter);
+        self
+    }
+
+    pub fn wrap(mut self, wrap: Wrap) -> Draw {
+        self.sampling.wrap = wrap;
+        self
+    }
+
+    /// Keep the texture smooth when it is drawn much smaller than it is
+    pub fn mipmaps(mut self, mipmaps: bool) -> Draw {
+        self.sampling.mipmaps = mipmaps;
+        self
+    }
+}
+
+pub struct DrawTilemap {
+    asset: String,
+    transform: Transform,
+    parent: Option<Transform>,
+}
+
+/// Draw every tile layer of a Tiled map in one go. One unit per tile, with the
+/// top left corner of the map where it is placed. Load it with
+/// [`crate::tilemap::load`] to read its objects and properties
+pub fn tilemap(asset: impl AsRef<str>) -> DrawTilemap {
+    DrawTilemap {
+        asset: asset.as_ref().into(),
+        transform: Transform::IDENTITY,
+        parent: None,
+    }
+}
+
+impl DrawTilemap {
+    pub fn pos(mut self, pos: impl Position) -> DrawTilemap {
+        self.transform.position = pos.to_vec3();
+        self
+    }
+
+    pub fn rot(mut self, rot: impl Rotation) -> DrawTilemap {
+        self.transform.rotation = rot.to_quat();
+        self
+    }
+
+    pub fn scale(mut self, scale: impl Position) -> DrawTilemap {
+        self.transform.scale = scale.to_vec3();
+        self
+    }
+
+    pub fn parent(mut self, parent: impl Parent) -> DrawTilemap {
+        self.parent = Some(parent.world());
+        self
+    }
+}
+
+impl Drop for DrawTilemap {
+    fn drop(&mut self) {
+        super::call_host(vg_types::Call::Tilemap(TilemapCall {
+            asset: self.asset.clone(),
+            trans: world(&self.parent, &self.transform),
+        }))
+    }
+}
+
+/// Save the frame being drawn as a PNG in `captures/`
+pub fn screenshot() {
+    super::call_host(vg_types::Call::Screenshot)
 }
 
 impl Drop for Draw {
     fn drop(&mut self) {
         super::call_host(vg_types::Call::Draw(vg_types::DrawCall {
             asset: self.asset.clone(),
-            trans: self.transform.clone(),
+            trans: world(&self.parent, &self.transform),
+            id: self.id,
+            sampling: self.sampling,
         }))
     }
 }
+
+fn world(parent: &Option<Transform>, local: &Transform) -> Transform {
+    match parent {
+        Some(parent) => combine(parent, local),
+        None => *local,
+    }
+}
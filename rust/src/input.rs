@@ -6,16 +6,29 @@ use crate::ensure;
 
 #[derive(Default)]
 pub struct Input {
-    keys: HashMap<Key, Digital>,
+    players: Vec<HashMap<Key, Digital>>,
+    local: usize,
 }
 
 impl Input {
     pub fn set(&mut self, key: Key, state: Digital) {
-        self.keys.insert(key, state);
+        self.set_player(self.local, key, state);
+    }
+
+    pub fn set_player(&mut self, player: usize, key: Key, state: Digital) {
+        if self.players.len() <= player {
+            self.players.resize_with(player + 1, HashMap::new);
+        }
+        self.players[player].insert(key, state);
+    }
+
+    pub fn set_players(&mut self, local: usize, count: usize) {
+        self.local = local;
+        self.players.resize_with(count.max(local + 1), HashMap::new);
     }
 
     pub fn step_states(&mut self) {
-        for state in self.keys.values_mut() {
+        for state in self.players.iter_mut().flat_map(|keys| keys.values_mut()) {
             match state {
                 Digital::Raised => *state = Digital::Up,
                 Digital::Pressed => *state = Digital::Down,
@@ -25,6 +38,55 @@ impl Input {
     }
 }
 
+/// Input of a single player. In online sessions the simulation should only
+/// read input through this, as the local player is different on every machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Player(pub usize);
+
+pub fn player(n: usize) -> Player {
+    Player(n)
+}
+
+/// The player sitting at this machine
+pub fn local_player() -> Player {
+    Player(ensure().input.local)
+}
+
+/// Number of players in the session
+pub fn players() -> usize {
+    ensure().input.players.len().max(1)
+}
+
+impl Player {
+    pub fn key(self, key: Key) -> Digital {
+        ensure()
+            .input
+            .players
+            .get(self.0)
+            .and_then(|keys| keys.get(&key))
+            .copied()
+            .unwrap_or(Digital::Up)
+    }
+
+    pub fn wasd(self) -> [f32; 2] {
+        let f = |k: Key| if *self.key(k) { 1.0 } else { 0.0 };
+
+        let mut x = 0.0;
+        let mut y = 0.0;
+        x += f(Key::D);
+        x += f(Key::Right);
+        x -= f(Key::A);
+        x -= f(Key::Left);
+
+        y += f(Key::W);
+        y += f(Key::Up);
+        y -= f(Key::S);
+        y -= f(Key::Down);
+
+        [x, y]
+    }
+}
+
 pub trait KeyExt: Sized {
     fn state(self) -> Digital;
 
@@ -56,34 +118,9 @@ impl KeyExt for Key {
 }
 
 pub fn key(key: Key) -> Digital {
-    ensure()
-        .input
-        .keys
-        .get(&key)
-        .copied()
-        .unwrap_or(Digital::Up)
+    local_player().key(key)
 }
 
 pub fn wasd() -> [f32; 2] {
-    fn f(k: Key) -> f32 {
-        if *key(k) {
-            1.0
-        } else {
-            0.0
-        }
-    }
-
-    let mut x = 0.0;
-    let mut y = 0.0;
-    x += f(Key::D);
-    x += f(Key::Right);
-    x -= f(Key::A);
-    x -= f(Key::Left);
-
-    y += f(Key::W);
-    y += f(Key::Up);
-    y -= f(Key::S);
-    y -= f(Key::Down);
-
-    [x, y]
+    local_player().wasd()
 }
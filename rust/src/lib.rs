@@ -1,14 +1,25 @@
 // pub use vg_derive::game;
 // use wasm_bindgen::prelude::*;
 
-use std::{collections::VecDeque, future::Future, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    time::Duration,
+};
+pub mod assets;
+mod config;
 mod conversions;
 mod executor;
+pub use config::*;
 pub use conversions::{Position, Rotation};
 use vg_types::DeBin;
 pub mod gfx;
 mod input;
+pub mod physics;
+pub mod reload;
+pub mod scene;
 pub mod sfx;
+pub mod tilemap;
 pub use input::*;
 
 #[macro_export]
@@ -17,19 +28,29 @@ macro_rules! game {
         fn main() {
             __vg_start($i)
         }
+
+        /// Entry point when loaded as a native library
+        #[no_mangle]
+        pub extern "C" fn __vg_main() {
+            __vg_start($i)
+        }
     };
 }
 
-#[cfg(target_os = "wasi")]
+#[cfg(any(target_os = "wasi", feature = "dylib"))]
 fn ensure() -> &'static mut State {
     unsafe { STATE.get_or_insert_with(|| unreachable!()) }
 }
 
 // This is what happens when you don't use cargo-vg
-#[cfg(not(target_os = "wasi"))]
+#[cfg(not(any(target_os = "wasi", feature = "dylib")))]
 fn ensure() -> &'static mut State {
     let mut code = Some(vg_builder::WASM.to_vec());
-    vg_native::Engine::run::<vg_native::runtime::wasm::Wasm, _>(move || code.take())
+    let opts = vg_native::Options {
+        embedded_assets: vg_builder::ASSETS,
+        ..Default::default()
+    };
+    vg_native::Engine::run_with::<vg_native::runtime::wasm::Wasm, _>(opts, move || code.take())
 }
 
 static mut STATE: Option<State> = None;
@@ -41,6 +62,13 @@ pub struct State {
     runtime: Duration,
     deltatime: Duration,
     input: Input,
+    save: Option<Box<dyn Fn() -> Vec<u8>>>,
+    restored: Option<Vec<u8>>,
+    asset_failures: Vec<assets::Failure>,
+    /// Assets the engine has finished preloading
+    preloaded: HashSet<String>,
+    /// Tile maps read by the engine, waiting to be picked up
+    tilemaps: HashMap<String, vg_types::MapData>,
 }
 
 #[link(wasm_import_module = "env")]
@@ -48,11 +76,28 @@ extern "C" {
     fn call(ptr: u64, len: u64);
 }
 
+/// Host function taking the place of the `call` import when we are a native
+/// library
+#[cfg(feature = "dylib")]
+static mut HOST: Option<extern "C" fn(ptr: u64, len: u64)> = None;
+
+#[cfg(feature = "dylib")]
+#[no_mangle]
+pub extern "C" fn __vg_connect(call: extern "C" fn(ptr: u64, len: u64)) {
+    unsafe { HOST = Some(call) }
+}
+
 pub fn __vg_start<F, Fut>(f: F)
 where
     F: Fn() -> Fut,
     Fut: Future<Output = ()> + 'static,
 {
+    // Tell the engine why we are about to trap
+    #[cfg(any(target_os = "wasi", feature = "dylib"))]
+    std::panic::set_hook(Box::new(|info| {
+        call_host(vg_types::Call::Crash(info.to_string()))
+    }));
+
     unsafe {
         STATE.get_or_insert_with(|| {
             let exec = executor::Executor::new(f());
@@ -66,6 +111,11 @@ where
                 runtime: Duration::from_secs(0),
                 deltatime: Duration::from_secs(0),
                 input: Input::default(),
+                save: None,
+                restored: None,
+                asset_failures: vec![],
+                preloaded: HashSet::new(),
+                tilemaps: HashMap::new(),
             }
         });
     }
@@ -78,6 +128,11 @@ pub extern "C" fn __vg_tick() {
     let state = ensure();
 
     state.tick += 1;
+
+    // unwinding into the host is undefined, the panic hook already told it why
+    #[cfg(feature = "dylib")]
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| state.exec.run()));
+    #[cfg(not(feature = "dylib"))]
     state.exec.run();
 }
 
@@ -90,6 +145,23 @@ pub extern "C" fn __vg_allocate(len: u64) -> u64 {
     resp.back().unwrap().as_ptr() as u64
 }
 
+/// Let the game save its state right before a hot reload
+#[no_mangle]
+pub extern "C" fn __vg_save() {
+    if let Some(save) = &ensure().save {
+        call_host(vg_types::Call::State(save()))
+    }
+}
+
+/// Give the host some way to pass state saved by a previous build
+#[no_mangle]
+pub extern "C" fn __vg_allocate_state(len: u64) -> u64 {
+    let restored = &mut ensure().restored;
+
+    *restored = Some(vec![0; len as usize]);
+    restored.as_ref().unwrap().as_ptr() as u64
+}
+
 /// Take a host-pushed Response and apply it to local state
 // #[no_mangle]
 // pub extern "C" fn __vg_consume() {
@@ -121,6 +193,13 @@ fn call_host(val: impl vg_types::SerBin) {
         let bytes = val.serialize_bin();
         call(bytes.as_ptr() as u64, bytes.len() as u64)
     }
+    #[cfg(feature = "dylib")]
+    unsafe {
+        let bytes = val.serialize_bin();
+        if let Some(call) = HOST {
+            call(bytes.as_ptr() as u64, bytes.len() as u64)
+        }
+    }
 }
 
 // Public api
@@ -142,6 +221,7 @@ pub async fn frame() {
     let state = ensure();
     state.exec.halt().await;
     state.input.step_states();
+    state.asset_failures.clear();
 
     while let Some(bytes) = state.responses.pop_front() {
         match vg_types::Response::deserialize_bin(&bytes).unwrap() {
@@ -154,6 +234,28 @@ pub async fn frame() {
             vg_types::Response::Tick => {
                 // state.input.tick();
             }
+            vg_types::Response::Players(local, count) => {
+                state.input.set_players(local as usize, count as usize)
+            }
+            vg_types::Response::PlayerUp(player, key) => {
+                state
+                    .input
+                    .set_player(player as usize, key, Digital::Raised)
+            }
+            vg_types::Response::PlayerDown(player, key) => {
+                state
+                    .input
+                    .set_player(player as usize, key, Digital::Pressed)
+            }
+            vg_types::Response::AssetFailed(asset, reason) => {
+                state.asset_failures.push(assets::Failure { asset, reason })
+            }
+            vg_types::Response::Preloaded(asset) => {
+                state.preloaded.insert(asset);
+            }
+            vg_types::Response::Tilemap(asset, map) => {
+                state.tilemaps.insert(asset, map);
+            }
         }
     }
 }
@@ -0,0 +1,831 @@
+use glam::Vec2;
+
+use crate::{tilemap::TileLayer, Position};
+
+/// Gap kept between a moved body and what it hit, so it can slide along
+/// without catching on the seams between neighbouring tiles
+const SKIN: f32 = 0.001;
+/// Most surfaces a single move slides along before it gives up
+const MAX_SLIDES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    /// Box that never rotates, by half its size
+    Aabb {
+        half: [f32; 2],
+    },
+    Circle {
+        radius: f32,
+    },
+    /// Upright capsule, `height` from the bottom of one end to the top of the
+    /// other
+    Capsule {
+        radius: f32,
+        height: f32,
+    },
+}
+
+impl Shape {
+    /// Every shape is a box with rounded corners, so two shapes collide like
+    /// a point and a bigger rounded box. Half size of the box and the radius
+    /// of its corners
+    fn rounded(self) -> (Vec2, f32) {
+        match self {
+            Shape::Aabb { half } => (half.into(), 0.0),
+            Shape::Circle { radius } => (Vec2::ZERO, radius),
+            Shape::Capsule { radius, height } => {
+                (Vec2::new(0.0, (height / 2.0 - radius).max(0.0)), radius)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Body {
+    /// Center of the shape
+    pub position: [f32; 2],
+    pub shape: Shape,
+    /// Only found by overlap queries, never blocks movement or rays
+    pub sensor: bool,
+    /// Free for the game to tell its bodies apart with
+    pub tag: u64,
+}
+
+impl Body {
+    pub fn aabb(position: impl Position, size: impl Position) -> Body {
+        let [width, height, _] = size.to_vec3();
+        Body::new(
+            position,
+            Shape::Aabb {
+                half: [width / 2.0, height / 2.0],
+            },
+        )
+    }
+
+    pub fn circle(position: impl Position, radius: f32) -> Body {
+        Body::new(position, Shape::Circle { radius })
+    }
+
+    pub fn capsule(position: impl Position, radius: f32, height: f32) -> Body {
+        Body::new(position, Shape::Capsule { radius, height })
+    }
+
+    pub fn new(position: impl Position, shape: Shape) -> Body {
+        let [x, y, _] = position.to_vec3();
+        Body {
+            position: [x, y],
+            shape,
+            sensor: false,
+            tag: 0,
+        }
+    }
+
+    pub fn sensor(mut self) -> Body {
+        self.sensor = true;
+        self
+    }
+
+    pub fn tag(mut self, tag: u64) -> Body {
+        self.tag = tag;
+        self
+    }
+}
+
+/// Handle to a body, stays invalid once the body is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BodyId {
+    index: usize,
+    generation: u32,
+}
+
+/// Something that was hit or overlapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Collider {
+    Body(BodyId),
+    /// A solid tile, by the grid it is in and its column and row
+    Tile {
+        grid: usize,
+        x: u32,
+        y: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub collider: Collider,
+    /// Pointing away from the surface that was hit
+    pub normal: [f32; 2],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Movement {
+    /// Where the body ended up
+    pub position: [f32; 2],
+    /// How far it actually moved
+    pub moved: [f32; 2],
+    /// Everything it slid along, in order
+    pub hits: Vec<Hit>,
+}
+
+impl Movement {
+    /// Whether the body landed on something below it
+    pub fn on_floor(&self) -> bool {
+        self.hits.iter().any(|hit| hit.normal[1] > 0.7)
+    }
+
+    pub fn on_ceiling(&self) -> bool {
+        self.hits.iter().any(|hit| hit.normal[1] < -0.7)
+    }
+
+    pub fn on_wall(&self) -> bool {
+        self.hits.iter().any(|hit| hit.normal[0].abs() > 0.7)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub collider: Collider,
+    pub point: [f32; 2],
+    pub normal: [f32; 2],
+    pub distance: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    generation: u32,
+    body: Option<Body>,
+}
+
+/// Solid tiles of a tile layer, one unit per tile like tile maps are drawn
+#[derive(Debug, Clone)]
+struct Grid {
+    /// Top left corner
+    origin: Vec2,
+    width: u32,
+    height: u32,
+    solid: Vec<bool>,
+}
+
+/// Bodies and static tile colliders. Plain arithmetic run by the game
+/// itself, so every copy of the game steps it exactly the same way and it
+/// rolls back along with everything else. Nothing moves on its own, bodies
+/// only go where they are moved
+#[derive(Debug, Clone, Default)]
+pub struct World {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    grids: Vec<Grid>,
+}
+
+impl World {
+    pub fn new() -> World {
+        World::default()
+    }
+
+    pub fn add(&mut self, body: Body) -> BodyId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.body = Some(body);
+                BodyId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    body: Some(body),
+                });
+                BodyId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: BodyId) -> Option<Body> {
+        let slot = self.slots.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+
+        let body = slot.body.take()?;
+        slot.generation += 1;
+        self.free.push(id.index);
+        Some(body)
+    }
+
+    pub fn get(&self, id: BodyId) -> Option<&Body> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .body
+            .as_ref()
+    }
+
+    /// Teleport or reshape a body. Use `move_and_slide` to move it without
+    /// going through anything
+    pub fn get_mut(&mut self, id: BodyId) -> Option<&mut Body> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .body
+            .as_mut()
+    }
+
+    pub fn bodies(&self) -> impl Iterator<Item = (BodyId, &Body)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = BodyId {
+                index,
+                generation: slot.generation,
+            };
+            Some((id, slot.body.as_ref()?))
+        })
+    }
+
+    /// Make the tiles of a layer solid where `solid` says so, with the top
+    /// left corner of the layer at `origin`. Returns the grid number used in
+    /// `Collider::Tile`
+    pub fn add_tiles(
+        &mut self,
+        layer: &TileLayer,
+        origin: impl Position,
+        solid: impl Fn(u32) -> bool,
+    ) -> usize {
+        let [x, y, _] = origin.to_vec3();
+        self.grids.push(Grid {
+            origin: Vec2::new(x, y),
+            width: layer.width,
+            height: layer.height,
+            solid: layer
+                .tiles
+                .iter()
+                .map(|tile| *tile != 0 && solid(*tile))
+                .collect(),
+        });
+
+        self.grids.len() - 1
+    }
+
+    pub fn clear_tiles(&mut self) {
+        self.grids.clear();
+    }
+
+    /// Move a body by `delta`, stopping at anything solid in the way and
+    /// sliding along it with whatever movement is left
+    pub fn move_and_slide(&mut self, id: BodyId, delta: impl Position) -> Movement {
+        let body = match self.get(id) {
+            Some(body) => body,
+            None => return Movement::default(),
+        };
+
+        let (half, radius) = body.shape.rounded();
+        let start = Vec2::from(body.position);
+        let [dx, dy, _] = delta.to_vec3();
+
+        let mut position = start;
+        let mut remaining = Vec2::new(dx, dy);
+        let mut hits = vec![];
+
+        for _ in 0..MAX_SLIDES {
+            if remaining.length_squared() <= f32::EPSILON * f32::EPSILON {
+                break;
+            }
+
+            let (t, normal, collider) = match self.first_hit(id, position, remaining, half, radius)
+            {
+                Some(hit) => hit,
+                None => {
+                    position += remaining;
+                    break;
+                }
+            };
+
+            position += remaining * t;
+            if t > 0.0 {
+                position += normal * SKIN;
+            }
+
+            // whatever is left goes along the surface
+            remaining *= 1.0 - t;
+            remaining -= normal * remaining.dot(normal);
+
+            hits.push(Hit {
+                collider,
+                normal: normal.into(),
+            });
+        }
+
+        if let Some(body) = self.get_mut(id) {
+            body.position = position.into();
+        }
+
+        Movement {
+            position: position.into(),
+            moved: (position - start).into(),
+            hits,
+        }
+    }
+
+    /// Everything a shape at `position` overlaps, sensors included
+    pub fn overlaps(&self, shape: Shape, position: impl Position) -> Vec<Collider> {
+        let [x, y, _] = position.to_vec3();
+        self.overlapping_with(None, shape, Vec2::new(x, y))
+    }
+
+    /// Everything overlapping a body, sensors included
+    pub fn overlapping(&self, id: BodyId) -> Vec<Collider> {
+        match self.get(id) {
+            Some(body) => self.overlapping_with(Some(id), body.shape, body.position.into()),
+            None => vec![],
+        }
+    }
+
+    /// First solid thing along a ray. Bodies the ray starts inside of are
+    /// not hit
+    pub fn raycast(
+        &self,
+        origin: impl Position,
+        direction: impl Position,
+        max_distance: f32,
+    ) -> Option<RayHit> {
+        let [x, y, _] = origin.to_vec3();
+        let [dx, dy, _] = direction.to_vec3();
+        let origin = Vec2::new(x, y);
+        let delta = Vec2::new(dx, dy).normalize_or_zero() * max_distance;
+
+        let mut best: Option<(f32, Vec2, Collider)> = None;
+        for (collider, center, half, radius) in self.solids(None, origin, delta, Vec2::ZERO, 0.0) {
+            let p = origin - center;
+            if overlap(p, half, radius) {
+                continue;
+            }
+
+            if let Some((t, normal)) = sweep(p, delta, half, radius) {
+                if best.map_or(true, |(best, ..)| t < best) {
+                    best = Some((t, normal, collider));
+                }
+            }
+        }
+
+        best.map(|(t, normal, collider)| RayHit {
+            collider,
+            point: (origin + delta * t).into(),
+            normal: normal.into(),
+            distance: t * max_distance,
+        })
+    }
+
+    /// Earliest solid thing a body moving by `delta` runs into
+    fn first_hit(
+        &self,
+        id: BodyId,
+        position: Vec2,
+        delta: Vec2,
+        half: Vec2,
+        radius: f32,
+    ) -> Option<(f32, Vec2, Collider)> {
+        let mut best: Option<(f32, Vec2, Collider)> = None;
+
+        for (collider, center, other_half, other_radius) in
+            self.solids(Some(id), position, delta, half, radius)
+        {
+            let p = position - center;
+            let (half, radius) = (half + other_half, radius + other_radius);
+
+            // already touching, only stop what moves further in
+            let hit = match touching(p, half, radius + SKIN * 2.0) {
+                Some(normal) if delta.dot(normal) < -1e-6 => Some((0.0, normal)),
+                Some(_) => None,
+                None => sweep(p, delta, half, radius),
+            };
+
+            if let Some((t, normal)) = hit {
+                if best.map_or(true, |(best, ..)| t < best) {
+                    best = Some((t, normal, collider));
+                }
+            }
+        }
+
+        best
+    }
+
+    fn overlapping_with(&self, id: Option<BodyId>, shape: Shape, position: Vec2) -> Vec<Collider> {
+        let (half, radius) = shape.rounded();
+
+        let bodies = self
+            .bodies()
+            .filter(|(other, _)| Some(*other) != id)
+            .map(|(other, body)| {
+                let (other_half, other_radius) = body.shape.rounded();
+                (
+                    Collider::Body(other),
+                    Vec2::from(body.position),
+                    other_half,
+                    other_radius,
+                )
+            });
+
+        bodies
+            .chain(self.tiles(position, Vec2::ZERO, half, radius))
+            .filter(|(_, center, other_half, other_radius)| {
+                overlap(
+                    position - *center,
+                    half + *other_half,
+                    radius + *other_radius,
+                )
+            })
+            .map(|(collider, ..)| collider)
+            .collect()
+    }
+
+    /// Solid bodies and tiles that could be in the way of a shape moving by
+    /// `delta`, with their centers and rounded box shapes
+    fn solids(
+        &self,
+        id: Option<BodyId>,
+        position: Vec2,
+        delta: Vec2,
+        half: Vec2,
+        radius: f32,
+    ) -> Vec<(Collider, Vec2, Vec2, f32)> {
+        let bodies = self
+            .bodies()
+            .filter(|(other, body)| Some(*other) != id && !body.sensor)
+            .map(|(other, body)| {
+                let (other_half, other_radius) = body.shape.rounded();
+                (
+                    Collider::Body(other),
+                    Vec2::from(body.position),
+                    other_half,
+                    other_radius,
+                )
+            });
+
+        bodies
+            .chain(self.tiles(position, delta, half, radius))
+            .collect()
+    }
+
+    /// Solid tiles near a shape moving by `delta`
+    fn tiles(
+        &self,
+        position: Vec2,
+        delta: Vec2,
+        half: Vec2,
+        radius: f32,
+    ) -> Vec<(Collider, Vec2, Vec2, f32)> {
+        let reach = half + Vec2::splat(radius + SKIN * 2.0);
+        let min = position.min(position + delta) - reach;
+        let max = position.max(position + delta) + reach;
+
+        let mut tiles = vec![];
+        for (index, grid) in self.grids.iter().enumerate() {
+            // rows go down from the origin
+            let columns = cells(min.x - grid.origin.x, max.x - grid.origin.x, grid.width);
+            let rows = cells(grid.origin.y - max.y, grid.origin.y - min.y, grid.height);
+
+            for y in rows {
+                for x in columns.clone() {
+                    let solid = grid.solid.get((y * grid.width + x) as usize);
+                    if solid != Some(&true) {
+                        continue;
+                    }
+
+                    let center = grid.origin + Vec2::new(x as f32 + 0.5, -(y as f32 + 0.5));
+                    tiles.push((
+                        Collider::Tile { grid: index, x, y },
+                        center,
+                        Vec2::splat(0.5),
+                        0.0,
+                    ));
+                }
+            }
+        }
+
+        tiles
+    }
+}
+
+/// Cells of a grid `len` long covering `from..to`
+fn cells(from: f32, to: f32, len: u32) -> std::ops::Range<u32> {
+    let start = from.floor().max(0.0) as u32;
+    let end = (to.floor() + 1.0).max(0.0).min(len as f32) as u32;
+    start.min(end)..end
+}
+
+/// Whether a point is strictly inside a rounded box around the origin
+fn overlap(p: Vec2, half: Vec2, radius: f32) -> bool {
+    if radius > 0.0 {
+        let offset = p - p.max(-half).min(half);
+        offset.length_squared() < radius * radius
+    } else {
+        p.x.abs() < half.x && p.y.abs() < half.y
+    }
+}
+
+/// Direction out of a rounded box around the origin, if a point is within
+/// `radius` of the box
+fn touching(p: Vec2, half: Vec2, radius: f32) -> Option<Vec2> {
+    let offset = p - p.max(-half).min(half);
+    let distance = offset.length_squared();
+    if distance > radius * radius {
+        return None;
+    }
+
+    if distance > f32::EPSILON {
+        return Some(offset / distance.sqrt());
+    }
+
+    // deep inside the box, out the closest side
+    if half.x - p.x.abs() < half.y - p.y.abs() {
+        Some(Vec2::new(1.0f32.copysign(p.x), 0.0))
+    } else {
+        Some(Vec2::new(0.0, 1.0f32.copysign(p.y)))
+    }
+}
+
+/// Earliest time in `0..=1` a point outside a rounded box around the origin
+/// runs into it moving by `delta`, and the surface normal there
+fn sweep(p: Vec2, delta: Vec2, half: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+    let outer = half + Vec2::splat(radius);
+    let (x_enter, x_exit) = slab(p.x, delta.x, outer.x)?;
+    let (y_enter, y_exit) = slab(p.y, delta.y, outer.y)?;
+
+    let enter = x_enter.max(y_enter);
+    let exit = x_exit.min(y_exit);
+    if enter > exit || enter > 1.0 || exit < 0.0 {
+        return None;
+    }
+
+    let normal = if x_enter > y_enter {
+        Vec2::new(-delta.x.signum(), 0.0)
+    } else {
+        Vec2::new(0.0, -delta.y.signum())
+    };
+
+    // the corners of the grown box are round
+    let enter = enter.max(0.0);
+    let hit = p + delta * enter;
+    if radius > 0.0 && hit.x.abs() > half.x && hit.y.abs() > half.y {
+        let corner = Vec2::new(half.x.copysign(hit.x), half.y.copysign(hit.y));
+        return circle(p - corner, delta, radius);
+    }
+
+    Some((enter, normal))
+}
+
+/// Times a point moving by `d` is within `-h..=h` along a single axis
+fn slab(o: f32, d: f32, h: f32) -> Option<(f32, f32)> {
+    if d.abs() <= f32::EPSILON {
+        return if o.abs() <= h {
+            Some((f32::NEG_INFINITY, f32::INFINITY))
+        } else {
+            None
+        };
+    }
+
+    let (a, b) = ((-h - o) / d, (h - o) / d);
+    Some((a.min(b), a.max(b)))
+}
+
+/// Earliest time in `0..=1` a point moving by `delta` runs into a circle
+/// around the origin
+fn circle(p: Vec2, delta: Vec2, radius: f32) -> Option<(f32, Vec2)> {
+    let a = delta.length_squared();
+    if a <= f32::EPSILON {
+        return None;
+    }
+
+    let b = p.dot(delta);
+    let c = p.length_squared() - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let t = (-b - discriminant.sqrt()) / a;
+    if !(0.0..=1.0).contains(&t) {
+        return None;
+    }
+
+    Some((t, (p + delta * t).normalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4
+    }
+
+    /// Layer of `rows` from the top, `#` being solid
+    fn layer(rows: &[&str]) -> TileLayer {
+        TileLayer {
+            name: "solid".into(),
+            width: rows[0].len() as u32,
+            height: rows.len() as u32,
+            tiles: rows
+                .iter()
+                .flat_map(|row| row.chars().map(|c| (c == '#') as u32))
+                .collect(),
+            properties: vec![],
+        }
+    }
+
+    #[test]
+    fn fast_bodies_do_not_tunnel() {
+        let mut world = World::new();
+        let wall = world.add(Body::aabb([10.0, 0.0], [0.1, 10.0]));
+        let id = world.add(Body::aabb([0.0, 0.0], [1.0, 1.0]));
+
+        let movement = world.move_and_slide(id, [1000.0, 0.0]);
+
+        assert!(close(movement.position, [9.45 - SKIN, 0.0]));
+        assert_eq!(world.get(id).unwrap().position, movement.position);
+        assert_eq!(movement.hits.len(), 1);
+        assert_eq!(movement.hits[0].collider, Collider::Body(wall));
+        assert!(movement.on_wall());
+        assert!(!movement.on_floor());
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let mut world = World::new();
+        world.add(Body::aabb([0.0, -1.0], [100.0, 2.0]));
+        let id = world.add(Body::aabb([0.0, 2.0], [1.0, 1.0]));
+
+        // lands a third of the way in and keeps all of its sideways movement
+        let movement = world.move_and_slide(id, [3.0, -4.0]);
+        assert!(close(movement.position, [3.0, 0.5 + SKIN]));
+        assert!(movement.on_floor());
+
+        // resting on the floor doesn't slow walking along it
+        let movement = world.move_and_slide(id, [2.0, -1.0]);
+        assert!(close(movement.position, [5.0, 0.5 + SKIN]));
+        assert!(close(movement.moved, [2.0, 0.0]));
+    }
+
+    #[test]
+    fn stops_in_corners() {
+        let mut world = World::new();
+        world.add(Body::aabb([0.0, -1.0], [100.0, 2.0]));
+        world.add(Body::aabb([5.0, 0.0], [2.0, 100.0]));
+        let id = world.add(Body::aabb([0.0, 3.0], [1.0, 1.0]));
+
+        let movement = world.move_and_slide(id, [10.0, -10.0]);
+
+        assert!(close(movement.position, [3.5 - SKIN, 0.5 + SKIN]));
+        assert_eq!(movement.hits.len(), 2);
+        assert!(movement.on_floor() && movement.on_wall());
+        assert!(world.overlapping(id).is_empty());
+    }
+
+    #[test]
+    fn gives_up_sliding_after_a_few_surfaces() {
+        // a narrow gap between two balls keeps deflecting a ball pushed into it
+        let mut world = World::new();
+        world.add(Body::circle([-1.45, 0.0], 1.0));
+        world.add(Body::circle([1.45, 0.0], 1.0));
+        let id = world.add(Body::circle([0.2, 3.0], 0.5));
+
+        let movement = world.move_and_slide(id, [0.0, -10.0]);
+
+        assert_eq!(movement.hits.len(), MAX_SLIDES);
+        assert!(world.overlapping(id).is_empty());
+    }
+
+    #[test]
+    fn rounded_shapes_meet_at_their_curves() {
+        let mut world = World::new();
+        world.add(Body::circle([0.0, 0.0], 1.0));
+        let id = world.add(Body::circle([-5.0, 0.0], 1.0));
+        let movement = world.move_and_slide(id, [10.0, 0.0]);
+        assert!(close(movement.position, [-2.0 - SKIN, 0.0]));
+
+        // a ball dropped onto the corner of a box stops one radius off it
+        let mut world = World::new();
+        world.add(Body::aabb([0.0, 0.0], [2.0, 2.0]));
+        let id = world.add(Body::circle([3.0, 3.0], 0.5));
+        let movement = world.move_and_slide(id, [-3.0, -3.0]);
+        let off = (0.5 + SKIN) / 2f32.sqrt();
+        assert!(close(movement.position, [1.0 + off, 1.0 + off]));
+        assert!(close(
+            movement.hits[0].normal,
+            [1.0 / 2f32.sqrt(), 1.0 / 2f32.sqrt()]
+        ));
+
+        // capsules stand on their round end
+        let mut world = World::new();
+        world.add(Body::aabb([0.0, -1.0], [100.0, 2.0]));
+        let id = world.add(Body::capsule([0.0, 5.0], 0.5, 2.0));
+        let movement = world.move_and_slide(id, [0.0, -10.0]);
+        assert!(close(movement.position, [0.0, 1.0 + SKIN]));
+    }
+
+    #[test]
+    fn collides_with_tiles() {
+        let mut world = World::new();
+        let grid = world.add_tiles(&layer(&["....", "....", "####"]), [0.0, 0.0], |_| true);
+        let id = world.add(Body::aabb([0.5, -0.5], [1.0, 1.0]));
+
+        let movement = world.move_and_slide(id, [2.0, -5.0]);
+
+        assert!(close(movement.position, [2.5, -1.5 + SKIN]));
+        assert!(matches!(
+            movement.hits[0].collider,
+            Collider::Tile { grid: g, y: 2, .. } if g == grid
+        ));
+
+        // only the tiles `solid` picks block
+        let mut world = World::new();
+        world.add_tiles(&layer(&["....", "....", "####"]), [0.0, 0.0], |_| false);
+        let id = world.add(Body::aabb([0.5, -0.5], [1.0, 1.0]));
+        assert!(world.move_and_slide(id, [0.0, -5.0]).hits.is_empty());
+    }
+
+    #[test]
+    fn rays_hit_the_nearest_solid() {
+        let mut world = World::new();
+        world.add_tiles(&layer(&["...", "..#", "..."]), [0.0, 0.0], |_| true);
+        let ball = world.add(Body::circle([0.0, 5.0], 1.0));
+        world.add(Body::circle([0.0, 2.0], 1.0).sensor());
+
+        let hit = world.raycast([0.0, -1.5], [1.0, 0.0], 10.0).unwrap();
+        assert_eq!(
+            hit.collider,
+            Collider::Tile {
+                grid: 0,
+                x: 2,
+                y: 1
+            }
+        );
+        assert!((hit.distance - 2.0).abs() < 1e-4);
+        assert!(close(hit.point, [2.0, -1.5]));
+        assert!(close(hit.normal, [-1.0, 0.0]));
+
+        // sensors are passed through
+        let hit = world.raycast([0.0, 0.0], [0.0, 2.0], 10.0).unwrap();
+        assert_eq!(hit.collider, Collider::Body(ball));
+        assert!((hit.distance - 4.0).abs() < 1e-4);
+        assert!(close(hit.normal, [0.0, -1.0]));
+
+        // too short, or starting inside
+        assert!(world.raycast([0.0, 0.0], [0.0, 1.0], 3.9).is_none());
+        assert!(world.raycast([0.0, 5.0], [0.0, 1.0], 10.0).is_none());
+    }
+
+    #[test]
+    fn finds_overlaps() {
+        let mut world = World::new();
+        let a = world.add(Body::aabb([0.0, 0.0], [2.0, 2.0]));
+        let sensor = world.add(Body::circle([1.5, 0.0], 1.0).sensor());
+        let far = world.add(Body::circle([10.0, 0.0], 1.0));
+
+        assert_eq!(world.overlapping(a), vec![Collider::Body(sensor)]);
+        assert_eq!(
+            world.overlaps(Shape::Circle { radius: 1.0 }, [1.0, 0.0]),
+            vec![Collider::Body(a), Collider::Body(sensor)]
+        );
+        // merely touching isn't overlapping
+        assert!(world.overlapping(far).is_empty());
+        assert!(world
+            .overlaps(Shape::Aabb { half: [1.0, 1.0] }, [-2.0, 0.0])
+            .is_empty());
+    }
+
+    #[test]
+    fn removed_bodies_stay_removed() {
+        let mut world = World::new();
+        let old = world.add(Body::circle([0.0, 0.0], 1.0).tag(1));
+        assert_eq!(world.remove(old).unwrap().tag, 1);
+
+        // the slot is reused, the old handle doesn't reach the new body
+        let new = world.add(Body::circle([0.0, 0.0], 1.0).tag(2));
+        assert_ne!(old, new);
+        assert!(world.get(old).is_none());
+        assert!(world.get_mut(old).is_none());
+        assert!(world.remove(old).is_none());
+        assert!(world.move_and_slide(old, [1.0, 0.0]).hits.is_empty());
+        assert_eq!(world.get(new).unwrap().position, [0.0, 0.0]);
+        assert_eq!(world.bodies().count(), 1);
+    }
+
+    #[test]
+    fn copies_step_the_same() {
+        let mut world = World::new();
+        world.add_tiles(&layer(&["#...#", "#...#", "#####"]), [0.0, 0.0], |_| true);
+        let id = world.add(Body::capsule([2.5, -0.5], 0.3, 0.9));
+        let mut copy = world.clone();
+
+        for i in 0..100 {
+            let delta = [(i as f32 * 0.7).sin() * 0.4, -0.2];
+            let a = world.move_and_slide(id, delta);
+            let b = copy.move_and_slide(id, delta);
+            assert_eq!(a.position, b.position);
+        }
+    }
+}
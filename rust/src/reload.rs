@@ -0,0 +1,34 @@
+use std::{cell::RefCell, rc::Rc};
+
+use vg_types::{DeBin, SerBin};
+
+use crate::ensure;
+
+/// Called right before the game is unloaded for a rebuild. The returned bytes
+/// are handed to the new build through `restored`
+pub fn on_save(save: impl Fn() -> Vec<u8> + 'static) {
+    ensure().save = Some(Box::new(save));
+}
+
+/// State saved by the previous build, if the game was just reloaded
+pub fn restored() -> Option<Vec<u8>> {
+    ensure().restored.take()
+}
+
+/// Save the value behind `state` whenever the game is reloaded, pairs with
+/// `restore` at startup:
+///
+/// ```ignore
+/// let world = Rc::new(RefCell::new(reload::restore().unwrap_or_else(World::new)));
+/// reload::persist(&world);
+/// ```
+pub fn persist<T: SerBin + 'static>(state: &Rc<RefCell<T>>) {
+    let state = Rc::clone(state);
+    on_save(move || state.borrow().serialize_bin());
+}
+
+/// Value saved with `persist` by the previous build. Returns `None` when the
+/// game was not reloaded, or the type can not be read back anymore
+pub fn restore<T: DeBin>() -> Option<T> {
+    T::deserialize_bin(&restored()?).ok()
+}
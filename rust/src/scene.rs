@@ -0,0 +1,332 @@
+use glam::{Quat, Vec3};
+pub use vg_types::Transform;
+
+use crate::{Position, Rotation};
+
+/// Handle to a node, stays invalid once the node is removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+/// A transform relative to its parent, or to the world when it has none
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub transform: Transform,
+    parent: Option<NodeId>,
+}
+
+impl Default for Node {
+    fn default() -> Node {
+        Node {
+            transform: Transform::IDENTITY,
+            parent: None,
+        }
+    }
+}
+
+impl Node {
+    pub fn new() -> Node {
+        Node::default()
+    }
+
+    pub fn pos(mut self, pos: impl Position) -> Node {
+        self.transform.position = pos.to_vec3();
+        self
+    }
+
+    pub fn rot(mut self, rot: impl Rotation) -> Node {
+        self.transform.rotation = rot.to_quat();
+        self
+    }
+
+    pub fn scale(mut self, scale: impl Position) -> Node {
+        self.transform.scale = scale.to_vec3();
+        self
+    }
+
+    pub fn parent(mut self, parent: NodeId) -> Node {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn get_parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// Nodes placed relative to each other, like a weapon on a ship. Only local
+/// transforms are stored, world transforms are worked out when asked for
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    pub fn add(&mut self, node: Node) -> NodeId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Remove a node along with everything below it
+    pub fn remove(&mut self, id: NodeId) -> Option<Node> {
+        let slot = self.slots.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+
+        let node = slot.node.take()?;
+        slot.generation += 1;
+        self.free.push(id.index);
+
+        let children: Vec<NodeId> = self
+            .nodes()
+            .filter(|(_, child)| child.parent == Some(id))
+            .map(|(child, _)| child)
+            .collect();
+        for child in children {
+            self.remove(child);
+        }
+
+        Some(node)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .node
+            .as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .node
+            .as_mut()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = NodeId {
+                index,
+                generation: slot.generation,
+            };
+            Some((id, slot.node.as_ref()?))
+        })
+    }
+
+    /// Move a node under another one, or to the top with `None`. Its local
+    /// transform is kept, so it jumps with its new parent. Nodes can't be
+    /// put below themselves, returns whether the node was moved
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+        let mut ancestor = parent;
+        while let Some(above) = ancestor {
+            if above == id {
+                return false;
+            }
+            ancestor = self.get(above).and_then(|node| node.parent);
+        }
+
+        match self.get_mut(id) {
+            Some(node) => {
+                node.parent = parent;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Transform of a node in the world, through all of its parents. A
+    /// removed parent counts as the world
+    pub fn world(&self, id: NodeId) -> Option<Transform> {
+        let mut node = self.get(id)?;
+        let mut world = node.transform;
+        while let Some(parent) = node.parent.and_then(|parent| self.get(parent)) {
+            world = combine(&parent.transform, &world);
+            node = parent;
+        }
+
+        Some(world)
+    }
+
+    /// Node to draw relative to with `.parent(..)`
+    pub fn node(&self, id: NodeId) -> NodeRef {
+        NodeRef { scene: self, id }
+    }
+}
+
+/// A node along with the scene it is in
+#[derive(Clone, Copy)]
+pub struct NodeRef<'a> {
+    scene: &'a Scene,
+    id: NodeId,
+}
+
+/// Anything draws can be placed relative to
+pub trait Parent {
+    fn world(&self) -> Transform;
+}
+
+impl Parent for Transform {
+    fn world(&self) -> Transform {
+        *self
+    }
+}
+
+impl Parent for NodeRef<'_> {
+    /// Removed nodes count as the world
+    fn world(&self) -> Transform {
+        self.scene.world(self.id).unwrap_or(Transform::IDENTITY)
+    }
+}
+
+/// `local` as seen from where `parent` is
+pub fn combine(parent: &Transform, local: &Transform) -> Transform {
+    let [x, y, z, w] = parent.rotation;
+    let rotation = Quat::from_xyzw(x, y, z, w);
+    let [x, y, z, w] = local.rotation;
+    let local_rotation = Quat::from_xyzw(x, y, z, w);
+    let scale = Vec3::from(parent.scale);
+
+    let position = Vec3::from(parent.position) + rotation * (scale * Vec3::from(local.position));
+
+    Transform {
+        position: position.into(),
+        scale: (scale * Vec3::from(local.scale)).into(),
+        rotation: (rotation * local_rotation).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn close(a: impl AsRef<[f32]>, b: impl AsRef<[f32]>) -> bool {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn combines_rotated_and_scaled_parents() {
+        let parent = Node::new().pos([10, 0]).rot(FRAC_PI_2).scale(2.0).transform;
+        let local = Node::new().pos([1, 0]).rot(FRAC_PI_2).scale(3.0).transform;
+
+        let world = combine(&parent, &local);
+        assert!(close(world.position, [10.0, 2.0, 0.0]));
+        assert!(close(world.scale, [6.0, 6.0, 6.0]));
+        assert!(close(world.rotation, [0.0, 0.0, 1.0, 0.0]));
+
+        let world = combine(&Transform::IDENTITY, &local);
+        assert!(close(world.position, local.position));
+        assert!(close(world.rotation, local.rotation));
+    }
+
+    #[test]
+    fn places_nodes_through_their_parents() {
+        let mut scene = Scene::new();
+        let ship = scene.add(Node::new().pos([10, 0]).rot(FRAC_PI_2).scale(2.0));
+        let turret = scene.add(Node::new().pos([1, 0]).parent(ship));
+        let barrel = scene.add(Node::new().pos([0, 1]).rot(FRAC_PI_2).parent(turret));
+
+        let world = scene.world(turret).unwrap();
+        assert!(close(world.position, [10.0, 2.0, 0.0]));
+
+        let world = scene.world(barrel).unwrap();
+        assert!(close(world.position, [8.0, 2.0, 0.0]));
+        assert!(close(world.scale, [2.0, 2.0, 2.0]));
+        assert!(close(world.rotation, [0.0, 0.0, 1.0, 0.0]));
+
+        // Moving the ship moves everything on it
+        scene.get_mut(ship).unwrap().transform.position = [0.0; 3];
+        let world = scene.world(barrel).unwrap();
+        assert!(close(world.position, [-2.0, 2.0, 0.0]));
+    }
+
+    #[test]
+    fn refuses_cycles() {
+        let mut scene = Scene::new();
+        let a = scene.add(Node::new());
+        let b = scene.add(Node::new().parent(a));
+        let c = scene.add(Node::new().parent(b));
+
+        assert!(!scene.set_parent(a, Some(a)));
+        assert!(!scene.set_parent(a, Some(c)));
+        assert_eq!(scene.get(a).unwrap().get_parent(), None);
+
+        assert!(scene.set_parent(c, Some(a)));
+        assert!(scene.set_parent(c, None));
+        assert_eq!(scene.get(c).unwrap().get_parent(), None);
+    }
+
+    #[test]
+    fn removes_children_with_their_parent() {
+        let mut scene = Scene::new();
+        let a = scene.add(Node::new());
+        let b = scene.add(Node::new().parent(a));
+        let c = scene.add(Node::new().parent(b));
+        let other = scene.add(Node::new());
+
+        assert!(scene.remove(a).is_some());
+        assert!(scene.remove(a).is_none());
+        for &id in &[a, b, c] {
+            assert!(scene.get(id).is_none());
+            assert!(scene.world(id).is_none());
+        }
+        assert_eq!(
+            scene.nodes().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![other]
+        );
+    }
+
+    #[test]
+    fn reuses_slots_with_new_generations() {
+        let mut scene = Scene::new();
+        let old = scene.add(Node::new().pos([1, 0]));
+        scene.remove(old);
+
+        let new = scene.add(Node::new().pos([2, 0]));
+        assert_ne!(old, new);
+        assert!(scene.get(old).is_none());
+        assert!(scene.get_mut(old).is_none());
+        assert!(!scene.set_parent(old, None));
+        assert!(scene.remove(old).is_none());
+        assert_eq!(scene.get(new).unwrap().transform.position, [2.0, 0.0, 0.0]);
+
+        // Children of the old node are not moved under the new one
+        let child = scene.add(Node::new().parent(old));
+        assert!(close(scene.world(child).unwrap().position, [0.0; 3]));
+    }
+}
@@ -0,0 +1,23 @@
+pub use vg_types::{
+    property, MapData as Map, MapObject, ObjectLayer, Property, PropertyValue, TileLayer,
+};
+
+use crate::{assets, call_host, ensure};
+
+/// Read a Tiled map (`.tmx` or `.tmj`/`.json`) for its layers, objects and
+/// properties, waiting out frames until the engine has it. Drawing it is done
+/// with [`crate::gfx::tilemap`]
+pub async fn load(asset: &str) -> Result<Map, String> {
+    loop {
+        if let Some(map) = ensure().tilemaps.remove(asset) {
+            return Ok(map);
+        }
+
+        if let Some(failure) = assets::failures().iter().find(|f| f.asset == asset) {
+            return Err(failure.reason.clone());
+        }
+
+        call_host(vg_types::Call::LoadTilemap(asset.into()));
+        crate::frame().await;
+    }
+}
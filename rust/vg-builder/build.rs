@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::env::var;
 use std::path::PathBuf;
 
@@ -53,8 +54,52 @@ fn build_wasm() {
     std::fs::copy(out_file, build_path.join("out.wasm")).unwrap();
 }
 
+// Write the table of embedded assets, empty unless asked for
+fn embed_assets() -> Result<(), String> {
+    let mut table = String::from("&[\n");
+
+    if var("CARGO_FEATURE_EMBED_ASSETS").is_ok() {
+        let mut embedded = HashSet::new();
+
+        for dir in vg_types::asset_dirs(&root_manifest())? {
+            if !dir.is_dir() {
+                continue;
+            }
+            println!("cargo:rerun-if-changed={}", dir.display());
+
+            let files = vg_types::asset_files(&dir)
+                .map_err(|e| format!("Failed to list assets in {}: {}", dir.display(), e))?;
+
+            // earlier directories take priority
+            for (name, path) in files {
+                let path = path
+                    .canonicalize()
+                    .map_err(|e| format!("Failed to find {}: {}", path.display(), e))?;
+                if embedded.insert(name.clone()) {
+                    table += &format!("    ({:?}, include_bytes!({:?})),\n", name, path);
+                }
+            }
+        }
+
+        println!("Embedded {} assets", embedded.len());
+    }
+
+    table += "]\n";
+
+    let out: PathBuf = var("OUT_DIR").unwrap().parse().unwrap();
+    std::fs::write(out.join("assets.rs"), table).map_err(|e| e.to_string())
+}
+
 fn main() {
+    if var("CARGO_FEATURE_NATIVE").is_ok() {
+        return;
+    }
+
     if std::env::var("TARGET").unwrap() != TARGET {
-        build_wasm()
+        build_wasm();
+        if let Err(e) = embed_assets() {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
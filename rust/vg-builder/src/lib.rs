@@ -1 +1,7 @@
+#[cfg(not(feature = "native"))]
 pub const WASM: &'static [u8] = include_bytes!(concat!(env!("OUT_DIR"), "/magic-build/out.wasm"));
+
+/// Assets by name, when built with the `embed-assets` feature
+#[cfg(not(feature = "native"))]
+pub const ASSETS: &'static [(&'static str, &'static [u8])] =
+    include!(concat!(env!("OUT_DIR"), "/assets.rs"));
@@ -1,6 +1,9 @@
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
-pub use nanoserde::{DeBin, SerBin};
+pub use nanoserde::{DeBin, DeJson, SerBin, SerJson};
 
 type Vec3 = [f32; 3];
 type Quat = [f32; 4];
@@ -24,12 +27,24 @@ impl Transform {
 pub enum Call {
     /// Exit the game
     Exit,
+    /// Change window and timing settings, keeping those left out
+    Configure(ConfigChange),
 
     // Graphics
     /// Finish a single frame of the game and return to host
     Present,
     /// Draw an asset with specified transform
     Draw(DrawCall),
+    /// Draw the tile layers of a tile map
+    Tilemap(TilemapCall),
+    /// Save the frame being drawn to the captures directory
+    Screenshot,
+
+    // Assets
+    /// Decode and upload assets ahead of their first use
+    Preload(Vec<String>),
+    /// Read a tile map, answered with `Response::Tilemap`
+    LoadTilemap(String),
 
     // Sound
     Play(PlayCall),
@@ -37,12 +52,247 @@ pub enum Call {
     // Debugging
     /// Print a log message
     Print(String),
+    /// Game panicked with a message and is about to trap
+    Crash(String),
+
+    // Hot reloading
+    /// Game state saved right before the game is reloaded
+    State(Vec<u8>),
+}
+
+#[derive(SerBin, DeBin, Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Title of the game window
+    pub title: String,
+    /// Inner size of the window in physical pixels
+    pub size: [u32; 2],
+    pub resizable: bool,
+    pub fullscreen: bool,
+    /// Fixed simulation ticks per second
+    pub tick_rate: f64,
+    /// Most ticks run in a single frame to catch up with wall time, anything
+    /// further behind is dropped
+    pub max_catch_up: u32,
+    /// Wait for vertical blank when presenting
+    pub vsync: bool,
+    /// Maximum presented frames per second
+    pub frame_cap: Option<f64>,
+    /// How frames between fixed ticks are produced
+    pub presentation: Presentation,
+}
+
+/// Settings a game changes, those left `None` keep their current value
+#[derive(SerBin, DeBin, Debug, Clone, Default, PartialEq)]
+pub struct ConfigChange {
+    pub title: Option<String>,
+    pub size: Option<[u32; 2]>,
+    pub resizable: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub tick_rate: Option<f64>,
+    pub max_catch_up: Option<u32>,
+    pub vsync: Option<bool>,
+    /// `Some(None)` lifts the cap
+    pub frame_cap: Option<Option<f64>>,
+    pub presentation: Option<Presentation>,
+}
+
+impl Config {
+    /// This configuration with the settings a game changed
+    pub fn merge(&self, change: ConfigChange) -> Config {
+        Config {
+            title: change.title.unwrap_or_else(|| self.title.clone()),
+            size: change.size.unwrap_or(self.size),
+            resizable: change.resizable.unwrap_or(self.resizable),
+            fullscreen: change.fullscreen.unwrap_or(self.fullscreen),
+            tick_rate: change.tick_rate.unwrap_or(self.tick_rate),
+            max_catch_up: change.max_catch_up.unwrap_or(self.max_catch_up),
+            vsync: change.vsync.unwrap_or(self.vsync),
+            frame_cap: change.frame_cap.unwrap_or(self.frame_cap),
+            presentation: change.presentation.unwrap_or(self.presentation),
+        }
+    }
+}
+
+#[derive(SerBin, DeBin, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Presentation {
+    /// Run the game ahead of the last tick on a copy of its state
+    Speculative,
+    /// Blend the draws of the last two ticks, matched by their id
+    Interpolated,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            title: "vg-main".into(),
+            size: [1280, 720],
+            resizable: true,
+            fullscreen: false,
+            tick_rate: 100.0,
+            max_catch_up: 8,
+            vsync: false,
+            frame_cap: None,
+            presentation: Presentation::Speculative,
+        }
+    }
 }
 
 #[derive(SerBin, DeBin, Debug, Clone)]
 pub struct DrawCall {
     pub asset: String,
     pub trans: Transform,
+    /// Identifies the same sprite across ticks
+    pub id: Option<u64>,
+    pub sampling: Sampling,
+}
+
+/// How a sprite's texture is sampled
+#[derive(SerBin, DeBin, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Sampling {
+    /// Picked from the image size when not set, nearest for small images
+    pub filter: Option<Filter>,
+    pub wrap: Wrap,
+    /// Generate smaller versions of the texture for when it is drawn small
+    pub mipmaps: bool,
+}
+
+impl Default for Sampling {
+    fn default() -> Sampling {
+        Sampling {
+            filter: None,
+            wrap: Wrap::Repeat,
+            mipmaps: false,
+        }
+    }
+}
+
+#[derive(SerBin, DeBin, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Filter {
+    /// Sharp pixels, for pixel art
+    Nearest,
+    /// Smooth blending between pixels
+    Linear,
+}
+
+/// What is sampled past the edges of a texture, shows along the edges of
+/// sprites with linear filtering
+#[derive(SerBin, DeBin, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(SerBin, DeBin, Debug, Clone)]
+pub struct TilemapCall {
+    pub asset: String,
+    pub trans: Transform,
+}
+
+/// Everything about a Tiled map besides how it looks. Positions are in tiles
+/// from the top left corner of the map, with y growing upwards like the rest
+/// of the world, so rows go down into negative y
+#[derive(SerBin, DeBin, Debug, Clone)]
+pub struct MapData {
+    /// Size in tiles
+    pub width: u32,
+    pub height: u32,
+    /// Size of a tile in pixels
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub properties: Vec<Property>,
+    pub layers: Vec<TileLayer>,
+    pub object_layers: Vec<ObjectLayer>,
+    /// Properties of the tiles that have any, by global tile id
+    pub tile_properties: Vec<(u32, Vec<Property>)>,
+}
+
+impl MapData {
+    pub fn layer(&self, name: &str) -> Option<&TileLayer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn object_layer(&self, name: &str) -> Option<&ObjectLayer> {
+        self.object_layers.iter().find(|layer| layer.name == name)
+    }
+
+    /// Properties set on a tile in its tileset, empty if it has none
+    pub fn tile_properties(&self, tile: u32) -> &[Property] {
+        self.tile_properties
+            .iter()
+            .find(|(id, _)| *id == tile)
+            .map_or(&[], |(_, properties)| properties)
+    }
+}
+
+#[derive(SerBin, DeBin, Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Global tile ids row by row from the top, 0 where there is no tile
+    pub tiles: Vec<u32>,
+    pub properties: Vec<Property>,
+}
+
+impl TileLayer {
+    /// Tile at a column and row counted from the top left, `None` where
+    /// there is no tile
+    pub fn get(&self, x: u32, y: u32) -> Option<u32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        self.tiles
+            .get((y * self.width + x) as usize)
+            .copied()
+            .filter(|tile| *tile != 0)
+    }
+}
+
+#[derive(SerBin, DeBin, Debug, Clone)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<MapObject>,
+    pub properties: Vec<Property>,
+}
+
+#[derive(SerBin, DeBin, Debug, Clone)]
+pub struct MapObject {
+    pub id: u32,
+    pub name: String,
+    /// Class of the object, called type in older versions of Tiled
+    pub kind: String,
+    /// Top left corner, in tiles
+    pub position: [f32; 2],
+    /// Size in tiles, zero for points
+    pub size: [f32; 2],
+    /// Tile the object is drawn as, if it is a tile object
+    pub tile: Option<u32>,
+    pub properties: Vec<Property>,
+}
+
+#[derive(SerBin, DeBin, Debug, Clone, PartialEq)]
+pub struct Property {
+    pub name: String,
+    pub value: PropertyValue,
+}
+
+/// Value of the property with a name, if there is one
+pub fn property<'a>(properties: &'a [Property], name: &str) -> Option<&'a PropertyValue> {
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .map(|property| &property.value)
+}
+
+#[derive(SerBin, DeBin, Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    /// Strings, and everything else Tiled has like colors and files
+    String(String),
 }
 
 #[derive(SerBin, DeBin, Debug, Clone)]
@@ -51,12 +301,26 @@ pub struct PlayCall {
     pub looping: bool,
 }
 
-#[derive(SerBin, DeBin, Debug)]
+#[derive(SerBin, DeBin, Debug, Clone)]
 pub enum Response {
     Time(f64),
     Up(Key),
     Down(Key),
     Tick,
+    /// Online session started, with the local player index and player count
+    Players(u8, u8),
+    /// Key pressed by a specific player
+    PlayerDown(u8, Key),
+    /// Key released by a specific player
+    PlayerUp(u8, Key),
+    /// Asset could not be loaded, with the reason why. Sent once, right
+    /// before the tick after the first one that used it
+    AssetFailed(String, String),
+    /// Asset asked for with `Call::Preload` is ready, or has failed. Sent
+    /// right before the first tick after it finished decoding
+    Preloaded(String),
+    /// Tile map asked for with `Call::LoadTilemap`
+    Tilemap(String, MapData),
 }
 
 #[derive(SerBin, DeBin, Debug, Hash, Eq, PartialEq, Clone, Copy)]
@@ -108,6 +372,56 @@ pub enum Key {
     Escape,
 }
 
+impl Key {
+    pub const ALL: [Key; 45] = [
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+        Key::Left,
+        Key::Right,
+        Key::Up,
+        Key::Down,
+        Key::Space,
+        Key::Shift,
+        Key::Control,
+        Key::Tab,
+        Key::Num1,
+        Key::Num2,
+        Key::Num3,
+        Key::Num4,
+        Key::Num5,
+        Key::Num6,
+        Key::Num7,
+        Key::Num8,
+        Key::Num9,
+        Key::Num0,
+        Key::Escape,
+    ];
+}
+
 #[derive(SerBin, DeBin, Debug, Copy, Clone)]
 pub enum Digital {
     Up,
@@ -126,3 +440,89 @@ impl Deref for Digital {
         }
     }
 }
+
+/// Every file under `dir` along with its asset name: the path relative to
+/// `dir` with `/` separators. Sorted by name, so the order is the same on
+/// every platform
+pub fn asset_files(dir: &Path) -> std::io::Result<Vec<(String, PathBuf)>> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(root, &path, files)?;
+                continue;
+            }
+
+            let name = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, path));
+        }
+
+        Ok(())
+    }
+
+    let mut files = vec![];
+    walk(dir, dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// The `[package.metadata.vg]` table of a manifest, if it has one
+#[cfg(feature = "manifest")]
+pub fn metadata(manifest: &Path) -> Result<Option<toml::Value>, String> {
+    let toml: toml::Value = std::fs::read_to_string(manifest)
+        .map_err(|e| format!("Failed to read {}: {}", manifest.display(), e))?
+        .parse()
+        .map_err(|e| format!("Failed to parse {}: {}", manifest.display(), e))?;
+
+    let vg = toml
+        .get("package")
+        .and_then(|package| package.get("metadata")?.get("vg"));
+    Ok(vg.cloned())
+}
+
+/// Asset directories of a project, in priority order. Read from
+/// `[package.metadata.vg]` in its manifest, relative to it:
+///
+/// ```toml
+/// [package.metadata.vg]
+/// assets = ["assets", "../shared-assets"]
+/// overrides = ["mods"]
+/// ```
+///
+/// Overrides are optional and searched first, so mods can replace any asset
+#[cfg(feature = "manifest")]
+pub fn asset_dirs(manifest: &Path) -> Result<Vec<PathBuf>, String> {
+    let root = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let metadata = metadata(manifest)?;
+
+    let list = |key: &str| -> Vec<PathBuf> {
+        metadata
+            .as_ref()
+            .and_then(|vg| vg.get(key)?.as_array().cloned())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| path.as_str())
+            .map(|path| root.join(path))
+            .collect()
+    };
+
+    let mut paths: Vec<PathBuf> = list("overrides")
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect();
+
+    let assets = list("assets");
+    if assets.is_empty() {
+        paths.push(root.join("assets"));
+    } else {
+        paths.extend(assets);
+    }
+
+    Ok(paths)
+}